@@ -0,0 +1,93 @@
+//! Time taken by an `Analyser` per frame, for both sample types.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rustfft::num_traits::Float;
+use rustfft::FFTnum;
+
+use offkey::analyser::{Analyser, ChannelSettings, Thresholds};
+use offkey::detector::{DetectorSettings, Estimator};
+use offkey::generator::{Generator, SignalSettings, Waveform};
+use offkey::source::IterSource;
+use offkey::stft::FrameSettings;
+use offkey::window::WindowKind;
+
+const SAMPLE_RATE: f64 = 44100.0;
+/// Frame lengths timed, in milliseconds: a short one and the default of the
+/// spectral estimators.
+const LENGTHS: [usize; 2] = [100, 1750];
+
+fn analyser(c: &mut Criterion) {
+    bench_type::<f32>(c, "f32");
+    bench_type::<f64>(c, "f64");
+}
+
+fn bench_type<T>(c: &mut Criterion, type_name: &str)
+where
+    T: FFTnum + Float + Default,
+{
+    let mut group = c.benchmark_group(format!("analyser {}", type_name));
+    for &millis in LENGTHS.iter() {
+        if millis > 1000 {
+            // The slowest frames take milliseconds each.
+            group.sample_size(10);
+        }
+        for &estimator in Estimator::ALL.iter() {
+            let id = BenchmarkId::new(estimator.name(), format!("{} ms", millis));
+            group.bench_function(id, |b| {
+                let mut next_frame = frames::<T>(estimator, millis);
+                b.iter(&mut next_frame)
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Analyses of a steady sawtooth, one frame per call.
+fn frames<T>(estimator: Estimator, millis: usize) -> impl FnMut()
+where
+    T: FFTnum + Float + Default,
+{
+    // A second of it is made beforehand, so that the cost of generating it
+    // does not count. It holds a whole number of periods and so loops
+    // seamlessly.
+    let mut signal = SignalSettings::new(Waveform::Sawtooth, 110.0);
+    signal.duration = Some(1.0);
+    let samples = Generator::new(signal, SAMPLE_RATE)
+        .map(|s| T::from(s).unwrap_or_else(T::zero))
+        .collect::<Vec<_>>();
+    let source = IterSource::new(samples.into_iter().cycle(), SAMPLE_RATE)
+        .with_chunk_len(4096);
+    let detector = DetectorSettings {
+        estimator,
+        window: WindowKind::Hann,
+        harmonics: 5,
+        fft_size: None,
+    };
+    let frames = FrameSettings {
+        length_millis: millis,
+        hop_millis: 10,
+        shorter_millis: Vec::new(),
+    };
+    // Every frame goes to the detector, loud or not.
+    let thresholds = Thresholds {
+        min_level: f64::NEG_INFINITY,
+        min_confidence: 0.0,
+    };
+    let mut analyser = Analyser::new(
+        source,
+        frames,
+        move || detector.build(),
+        ChannelSettings::default(),
+        thresholds,
+    )
+    .expect("a mono source has a channel 1");
+    move || loop {
+        if analyser.next_analysis().is_some() {
+            return;
+        }
+        analyser.read_data().expect("generated audio does not fail");
+    }
+}
+
+criterion_group!(benches, analyser);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+//! `RealFft` against a complex FFT of the same frame, which is how the
+//! spectral estimators transformed it before, for both sample types.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Float;
+use rustfft::{FFTnum, FFTplanner};
+
+use offkey::generator::{Generator, SignalSettings, Waveform};
+use offkey::realfft::RealFft;
+
+const SAMPLE_RATE: f64 = 44100.0;
+/// Frames of 100 ms and 1750 ms, as they are and padded to a fast size.
+const LENGTHS: [usize; 4] = [4410, 4500, 77175, 77760];
+
+fn fft(c: &mut Criterion) {
+    bench_type::<f32>(c, "f32");
+    bench_type::<f64>(c, "f64");
+}
+
+fn bench_type<T: FFTnum + Float>(c: &mut Criterion, type_name: &str) {
+    let mut group = c.benchmark_group(format!("fft {}", type_name));
+    for &len in LENGTHS.iter() {
+        let id = |path: &str| BenchmarkId::new(path, len);
+        let frame = sawtooth::<T>(len);
+        let zero = Complex::new(T::zero(), T::zero());
+
+        let mut real = RealFft::new(len);
+        let mut spectrum = vec![zero; real.spectrum_len()];
+        group.bench_function(id("real"), |b| {
+            b.iter(|| {
+                real.process(black_box(&frame), &mut spectrum);
+                black_box(&spectrum);
+            })
+        });
+
+        let complex = FFTplanner::new(false).plan_fft(len);
+        let mut input = vec![zero; len];
+        let mut output = vec![zero; len];
+        group.bench_function(id("complex"), |b| {
+            b.iter(|| {
+                for (targ, &src) in input.iter_mut().zip(black_box(&frame)) {
+                    *targ = Complex::new(src, T::zero());
+                }
+                complex.process(&mut input, &mut output);
+                black_box(&output);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn sawtooth<T: Float>(len: usize) -> Vec<T> {
+    let signal = SignalSettings::new(Waveform::Sawtooth, 110.0);
+    Generator::new(signal, SAMPLE_RATE)
+        .take(len)
+        .map(|s| T::from(s).unwrap_or_else(T::zero))
+        .collect()
+}
+
+criterion_group!(benches, fft);
+criterion_main!(benches);
@@ -1,163 +1,238 @@
-use advanced_collections::circular_buffer::CircularBuffer;
-use alsa::pcm::{Format, IO, PCM};
-use itertools::Itertools;
-use rustfft::num_complex::Complex;
-use rustfft::num_traits::Num;
+use std::thread;
+use std::time::Duration;
 
-use crate::sample::{FromAnySample, FromSample, Normal};
+use alsa::pcm::{Format, Frames, HwParams, State, PCM};
+use alsa::poll::PollDescriptors;
+use alsa::ValueOr;
+use libc::pollfd;
+use nix::errno::Errno;
 
-use InputBuffer::*;
+use crate::sample::ByteOrder::{Big, Little};
+use crate::sample::Encoding::{Float, Signed, Unsigned};
+use crate::sample::{FromAnySample, RawFormat};
+use crate::source::{AudioSource, Xruns};
+
+/* ---------- constants ---------- */
+
+/// The device buffer is not grown past this much audio, in seconds, as the
+/// latency would be too much.
+const MAX_BUFFER_SECONDS: f64 = 1.0;
+/// How long to wait between attempts to resume a suspended device.
+const RESUME_DELAY: Duration = Duration::from_millis(100);
+
+/// The ALSA sample formats that can be read, with their ALSA names and how
+/// their samples are laid out.
+pub const FORMATS: [(Format, &str, RawFormat); 30] = [
+    (Format::S8, "S8", RawFormat::new(Signed, 1, Little)),
+    (Format::U8, "U8", RawFormat::new(Unsigned, 1, Little)),
+    (Format::S16LE, "S16_LE", RawFormat::new(Signed, 2, Little)),
+    (Format::S16BE, "S16_BE", RawFormat::new(Signed, 2, Big)),
+    (Format::U16LE, "U16_LE", RawFormat::new(Unsigned, 2, Little)),
+    (Format::U16BE, "U16_BE", RawFormat::new(Unsigned, 2, Big)),
+    // These take four bytes with the sample in the lowest three.
+    (Format::S24LE, "S24_LE", RawFormat::new(Signed, 4, Little).padded(24)),
+    (Format::S24BE, "S24_BE", RawFormat::new(Signed, 4, Big).padded(24)),
+    (Format::U24LE, "U24_LE", RawFormat::new(Unsigned, 4, Little).padded(24)),
+    (Format::U24BE, "U24_BE", RawFormat::new(Unsigned, 4, Big).padded(24)),
+    (Format::S32LE, "S32_LE", RawFormat::new(Signed, 4, Little)),
+    (Format::S32BE, "S32_BE", RawFormat::new(Signed, 4, Big)),
+    (Format::U32LE, "U32_LE", RawFormat::new(Unsigned, 4, Little)),
+    (Format::U32BE, "U32_BE", RawFormat::new(Unsigned, 4, Big)),
+    (Format::FloatLE, "FLOAT_LE", RawFormat::new(Float, 4, Little)),
+    (Format::FloatBE, "FLOAT_BE", RawFormat::new(Float, 4, Big)),
+    (Format::Float64LE, "FLOAT64_LE", RawFormat::new(Float, 8, Little)),
+    (Format::Float64BE, "FLOAT64_BE", RawFormat::new(Float, 8, Big)),
+    // Packed into three bytes.
+    (Format::S243LE, "S24_3LE", RawFormat::new(Signed, 3, Little)),
+    (Format::S243BE, "S24_3BE", RawFormat::new(Signed, 3, Big)),
+    (Format::U243LE, "U24_3LE", RawFormat::new(Unsigned, 3, Little)),
+    (Format::U243BE, "U24_3BE", RawFormat::new(Unsigned, 3, Big)),
+    (Format::S203LE, "S20_3LE", RawFormat::new(Signed, 3, Little).padded(20)),
+    (Format::S203BE, "S20_3BE", RawFormat::new(Signed, 3, Big).padded(20)),
+    (Format::U203LE, "U20_3LE", RawFormat::new(Unsigned, 3, Little).padded(20)),
+    (Format::U203BE, "U20_3BE", RawFormat::new(Unsigned, 3, Big).padded(20)),
+    (Format::S183LE, "S18_3LE", RawFormat::new(Signed, 3, Little).padded(18)),
+    (Format::S183BE, "S18_3BE", RawFormat::new(Signed, 3, Big).padded(18)),
+    (Format::U183LE, "U18_3LE", RawFormat::new(Unsigned, 3, Little).padded(18)),
+    (Format::U183BE, "U18_3BE", RawFormat::new(Unsigned, 3, Big).padded(18)),
+];
 
 /* ---------- main things ---------- */
 
-pub struct AlsaSource<'a, T> {
-    pub device: &'a PCM,
-    input: InputBuffer<'a>,
-    data: CircularBuffer<Complex<T>>,
+/// Audio captured from an ALSA device.
+///
+/// The device is read as raw bytes which are then decoded explicitly, so
+/// any byte order and the 24-bit formats work regardless of the machine.
+pub struct AlsaSource<T> {
+    device: PCM,
+    format: RawFormat,
+    /// Raw bytes from the device, a period's worth.
+    bytes: Vec<u8>,
+    /// Samples from the latest read.
+    data: Vec<T>,
     num_channels: usize,
     sample_frequency: f64,
+    xruns: Xruns,
+    /// Set while a suspended device is waking up, so that the suspension is
+    /// only counted once however many reads it takes.
+    suspended: bool,
 }
 
-pub enum InputBuffer<'a> {
-    I8(IO<'a, i8>, Vec<i8>),
-    U8(IO<'a, u8>, Vec<u8>),
-    I16(IO<'a, i16>, Vec<i16>),
-    U16(IO<'a, u16>, Vec<u16>),
-    I32(IO<'a, i32>, Vec<i32>),
-    U32(IO<'a, u32>, Vec<u32>),
-    F32(IO<'a, f32>, Vec<f32>),
-    F64(IO<'a, f64>, Vec<f64>),
-}
-
-impl<'a, T: Normal + Clone + Num> AlsaSource<'a, T> {
-    pub fn new(pcm: &'a PCM, buffer_millis: usize) -> alsa::Result<Self> {
-        use std::iter::repeat_with;
+impl<T> AlsaSource<T> {
+    /// Read from `pcm`, which has to be set up for interleaved access.
+    pub fn new(pcm: PCM) -> alsa::Result<Self> {
         let params = pcm.hw_params_current()?;
         let period_size = params.get_period_size()?.max(1) as usize;
+        let buffer_size = params.get_buffer_size()?.max(1) as usize;
         let num_channels = params.get_channels()?.max(1) as usize;
+        let format = raw_format(params.get_format()?)
+            .ok_or_else(|| alsa::Error::unsupported("Unsupported sample format"))?;
         let scratchsize = period_size * num_channels;
-        let io = match params.get_format()? {
-            Format::S8 => I8(pcm.io_i8()?, vec![0; scratchsize]),
-            Format::U8 => U8(pcm.io_u8()?, vec![0; scratchsize]),
-            Format::S16LE | Format::S16BE => I16(pcm.io_i16()?, vec![0; scratchsize]),
-            Format::U16LE | Format::U16BE => U16(pcm.io_u16()?, vec![0; scratchsize]),
-            Format::S32LE | Format::S32BE => I32(pcm.io_i32()?, vec![0; scratchsize]),
-            Format::U32LE | Format::U32BE => U32(pcm.io_u32()?, vec![0; scratchsize]),
-            Format::FloatLE | Format::FloatBE => {
-                F32(pcm.io_f32()?, vec![0.0; scratchsize])
-            }
-            Format::Float64LE | Format::Float64BE => {
-                F64(pcm.io_f64()?, vec![0.0; scratchsize])
-            }
-            _ => return Err(alsa::Error::unsupported("Unsupported sample format")),
-        };
         let rate = params.get_rate()?.max(1) as usize;
-        let buffer_size = rate * buffer_millis / 1000;
-        let buf = repeat_with(|| Complex::new(T::midpoint(), T::zero()))
-            .take(buffer_size)
-            .collect::<CircularBuffer<_>>();
+        drop(params);
+        start(&pcm)?;
         Ok(AlsaSource {
             device: pcm,
-            input: io,
-            data: buf,
+            format,
+            bytes: vec![0; scratchsize * format.width],
+            data: Vec::with_capacity(scratchsize),
             num_channels,
             sample_frequency: rate as f64,
+            xruns: Xruns {
+                period_size,
+                buffer_size,
+                ..Xruns::default()
+            },
+            suspended: false,
         })
     }
-}
 
-impl<'a, T: Clone> AlsaSource<'a, T> {
-    pub fn clone_fft_data(&self, data: &mut [Complex<T>]) {
-        for (targ, src) in data.iter_mut().zip(self.data.iter()) {
-            *targ = src.clone();
+    /// Set the device up again with twice the period and buffer, so that it
+    /// takes longer to overflow. Returns false if the buffer is already as
+    /// large as it is allowed to get, leaving the device alone.
+    fn grow(&mut self) -> alsa::Result<bool> {
+        let current = self.device.hw_params_current()?;
+        let period = current.get_period_size()?;
+        let buffer = current.get_buffer_size()?;
+        let limit = (self.sample_frequency * MAX_BUFFER_SECONDS) as Frames;
+        if buffer >= limit {
+            return Ok(false);
         }
+        let params = HwParams::any(&self.device)?;
+        params.set_access(current.get_access()?)?;
+        params.set_format(current.get_format()?)?;
+        params.set_channels(current.get_channels()?)?;
+        params.set_rate(current.get_rate()?, ValueOr::Nearest)?;
+        params.set_period_size_near(2 * period, ValueOr::Nearest)?;
+        params.set_buffer_size_near((2 * buffer).min(limit))?;
+        self.device.drop()?;
+        self.device.hw_params(&params)?;
+        let grown = self.device.hw_params_current()?;
+        let period_size = grown.get_period_size()?.max(1) as usize;
+        self.bytes.resize(period_size * self.num_channels * self.format.width, 0);
+        self.xruns.period_size = period_size;
+        self.xruns.buffer_size = grown.get_buffer_size()?.max(1) as usize;
+        Ok(true)
     }
-}
 
-impl<'a, T: FromAnySample + Num + Clone> AlsaSource<'a, T> {
-    pub fn read(&mut self) -> alsa::Result<()> {
-        let numch = self.num_channels;
-        match &mut self.input {
-            I8(io, scratch) => read_into_buf(&mut self.data, io, scratch, numch),
-            U8(io, scratch) => read_into_buf(&mut self.data, io, scratch, numch),
-            I16(io, scratch) => read_into_buf(&mut self.data, io, scratch, numch),
-            U16(io, scratch) => read_into_buf(&mut self.data, io, scratch, numch),
-            I32(io, scratch) => read_into_buf(&mut self.data, io, scratch, numch),
-            U32(io, scratch) => read_into_buf(&mut self.data, io, scratch, numch),
-            F32(io, scratch) => read_into_buf(&mut self.data, io, scratch, numch),
-            F64(io, scratch) => read_into_buf(&mut self.data, io, scratch, numch),
-        }
+    /// Try to wake a suspended device up, or start it afresh if it cannot be
+    /// resumed. If it is not awake yet, this waits a little and leaves it be:
+    /// the next read fails the same way and comes back here, which gives the
+    /// reader a chance to give up in between.
+    fn resume(&mut self) -> alsa::Result<()> {
+        let res = match self.device.resume() {
+            Err(e) if e.errno() == Some(Errno::EAGAIN) => {
+                thread::sleep(RESUME_DELAY);
+                return Ok(());
+            }
+            Ok(()) => Ok(()),
+            Err(_) => self.device.prepare(),
+        };
+        self.suspended = false;
+        res
     }
 }
 
-impl<'a, T> AlsaSource<'a, T> {
-    pub fn buf_len(&self) -> usize {
-        self.data.len()
+impl<T: FromAnySample> AudioSource<T> for AlsaSource<T> {
+    type Error = alsa::Error;
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_frequency
     }
 
-    pub fn expand_input_buffer(&mut self) {
-        self.input.increase_size()
+    fn channels(&self) -> usize {
+        self.num_channels
     }
 
-    pub fn frequency_at(&self, index: usize) -> f64 {
-        let index = index as f64;
-        let len = self.buf_len() as f64;
-        index * self.sample_frequency / len
+    fn read(&mut self) -> alsa::Result<&[T]> {
+        self.data.clear();
+        // The IO is only held for the read, as none may exist while the
+        // device is set up again. This is in frames, not samples or bytes.
+        let read = match self.device.io().readi(&mut self.bytes) {
+            Ok(read) => read,
+            // Nothing has arrived yet.
+            Err(e) if e.errno() == Some(Errno::EAGAIN) => 0,
+            Err(e) => return Err(e),
+        };
+        let len = read * self.num_channels * self.format.width;
+        self.format.decode(&self.bytes[..len], &mut self.data);
+        Ok(&self.data)
     }
-}
 
-/* ---------- helpers ---------- */
+    /// Carry on after an overrun or a suspend. The device is started again
+    /// straight away, as it would not wake up a `poll` otherwise.
+    fn recover(&mut self, error: alsa::Error) -> alsa::Result<()> {
+        let res = match error.errno() {
+            Some(Errno::EAGAIN) => Ok(()),
+            Some(Errno::EPIPE) => {
+                self.xruns.overruns += 1;
+                match self.grow() {
+                    // Setting the parameters prepares the device as well.
+                    Ok(true) => Ok(()),
+                    // Carry on with the old size rather than not at all.
+                    Ok(false) | Err(_) => self.device.try_recover(error, true),
+                }
+            }
+            Some(Errno::ESTRPIPE) => {
+                if !self.suspended {
+                    self.xruns.suspends += 1;
+                    self.suspended = true;
+                }
+                self.resume()
+            }
+            _ => Err(error),
+        };
+        res.and_then(|()| start(&self.device))
+    }
 
-impl<'a> InputBuffer<'a> {
-    fn increase_size(&mut self) {
-        match self {
-            I8(_, vector) => vector.reserve(vector.len()),
-            U8(_, vector) => vector.reserve(vector.len()),
-            I16(_, vector) => vector.reserve(vector.len()),
-            U16(_, vector) => vector.reserve(vector.len()),
-            I32(_, vector) => vector.reserve(vector.len()),
-            U32(_, vector) => vector.reserve(vector.len()),
-            F32(_, vector) => vector.reserve(vector.len()),
-            F64(_, vector) => vector.reserve(vector.len()),
-        }
+    fn poll_descriptors(&self) -> alsa::Result<Vec<pollfd>> {
+        self.device.get()
+    }
+
+    fn poll_ready(&self, descriptors: &[pollfd]) -> alsa::Result<bool> {
+        let events = self.device.revents(descriptors)?;
+        Ok(!events.is_empty())
+    }
+
+    fn xruns(&self) -> Option<Xruns> {
+        Some(self.xruns)
     }
 }
 
-fn read_into_buf<I, T>(
-    buf: &mut CircularBuffer<Complex<T>>,
-    io: &IO<'_, I>,
-    scratch: &mut [I],
-    num_channels: usize,
-) -> alsa::Result<()>
-where
-    I: Copy,
-    T: FromSample<I> + Num + Clone,
-{
-    let read = io.readi(scratch)? * num_channels;
-    buf.extend(
-        scratch
-            .iter()
-            .take(read * num_channels)
-            .cloned()
-            .map(T::from_sample)
-            .chunks(num_channels)
-            .into_iter()
-            .map(average)
-            .map(|r| Complex::new(r, T::zero())),
-    );
+/* ---------- helpers ---------- */
+
+/// Start capturing if the device is ready to but has not started yet.
+fn start(device: &PCM) -> alsa::Result<()> {
+    if device.state() == State::Prepared {
+        device.start()?;
+    }
     Ok(())
 }
 
-fn average<T, I>(iter: T) -> I
-where
-    I: Num,
-    T: Iterator<Item = I>,
-{
-    let mut count = I::zero();
-    let mut total = I::zero();
-    for v in iter {
-        count = count + I::one();
-        total = total + v;
-    }
-    total / count
+/// How samples in an ALSA format are laid out, if they can be decoded.
+fn raw_format(format: Format) -> Option<RawFormat> {
+    FORMATS
+        .iter()
+        .find(|(known, _, _)| *known == format)
+        .map(|(_, _, raw)| *raw)
 }
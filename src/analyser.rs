@@ -1,95 +1,559 @@
-use std::sync::Arc;
+use libc::pollfd;
+use rustfft::num_traits::Float;
+use snafu::Snafu;
 
-use alsa::pcm::PCM;
-use ordered_float::NotNan;
-use nix::errno::Errno;
-use rustfft::num_complex::Complex;
-use rustfft::num_traits::{Float, Num};
-use rustfft::{FFTnum, FFTplanner, FFT};
+use crate::detector::{Estimate, PitchDetector};
+use crate::source::{AudioSource, Xruns};
+use crate::stft::{FrameSettings, Stft, Timestamp};
 
-use crate::alsa_source::AlsaSource;
-use crate::sample::{FromAnySample, Normal};
+/* ---------- constants ---------- */
 
-/* ---------- main things ---------- */
+/// A frame shorter than the main one has to span this many periods of a note
+/// for its estimate to be used.
+const MIN_PERIODS: f64 = 10.0;
+
+/* ---------- types ---------- */
+
+/// Cuts the audio from a source into frames and finds the pitch of each.
+///
+/// The selected channels of the source are either mixed together and
+/// analysed as one, or each analysed on its own.
+///
+/// Besides the main frame, shorter ones can be cut from its end, each with
+/// a detector of its own. They are tried from the shortest up, and the first
+/// one with an estimate high enough for it to span `MIN_PERIODS` periods of
+/// is used, falling back to the main frame. This way high notes, which short
+/// frames resolve well, are heard as soon as they start, while low notes
+/// still get the whole of the main frame.
+pub struct Analyser<T, S> {
+    source: S,
+    /// Channels of the source to use.
+    selected: Vec<usize>,
+    streams: Vec<Stream<T>>,
+    /// The stream to look for a frame in first.
+    next_stream: usize,
+    thresholds: Thresholds,
+}
+
+/// Which channels are analysed, and how.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChannelSettings {
+    /// Channels to use, counting from 0. All of them if empty.
+    pub selected: Vec<usize>,
+    /// Analyse each channel on its own rather than their mix.
+    pub separate: bool,
+}
 
-pub struct Analyser<'a, T> {
-    fft: Arc<dyn FFT<T>>,
-    fft_input: Vec<Complex<T>>,
-    fft_output: Vec<Complex<T>>,
-    alsa_source: AlsaSource<'a, T>,
+/// Anything below these is not considered a note.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// RMS level, in dB relative to full scale.
+    pub min_level: f64,
+    pub min_confidence: f64,
 }
 
-impl<'a, T> Analyser<'a, T>
+/// What came out of analysing a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Analysis {
+    /// The channel analysed, or `None` for a mix of channels.
+    pub channel: Option<usize>,
+    /// The audio this analysis covers.
+    pub time: Timestamp,
+    /// RMS level, in dB relative to full scale.
+    pub level: f64,
+    /// The pitch, if the signal is loud and clear enough.
+    pub estimate: Option<Estimate>,
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum SetupError {
+    #[snafu(display(
+        "No channel {} in a source with {} channels",
+        channel + 1,
+        available
+    ))]
+    NoSuchChannel { channel: usize, available: usize },
+}
+
+/// A single stream of samples with its own framing and detectors.
+struct Stream<T> {
+    channel: Option<usize>,
+    stft: Stft<T>,
+    samples: Vec<T>,
+    /// From the shortest frame to the main one.
+    resolutions: Vec<Resolution<T>>,
+}
+
+/// A frame length, with a detector for frames of that length.
+struct Resolution<T> {
+    /// Number of samples at the end of the main frame that this takes.
+    len: usize,
+    detector: Box<dyn PitchDetector<T>>,
+}
+
+/* ---------- main things ---------- */
+
+impl<T, S> Analyser<T, S>
 where
-    T: FFTnum + Normal + Default,
+    T: Default,
+    S: AudioSource<T>,
 {
-    pub fn new(pcm: &'a PCM, millis_for_analysis: usize) -> alsa::Result<Self> {
-        let alsa_source = AlsaSource::new(pcm, millis_for_analysis)?;
-        let bufsize = alsa_source.buf_len();
-        let mut planner = FFTplanner::new(false);
+    /// Set up the analysis. `detector` is called for every stream that needs
+    /// a detector of its own.
+    pub fn new<F>(
+        source: S,
+        frames: FrameSettings,
+        mut detector: F,
+        channels: ChannelSettings,
+        thresholds: Thresholds,
+    ) -> Result<Self, SetupError>
+    where
+        F: FnMut() -> Box<dyn PitchDetector<T>>,
+    {
+        let available = source.channels();
+        let selected = if channels.selected.is_empty() {
+            (0..available).collect()
+        } else {
+            channels.selected
+        };
+        if let Some(&channel) = selected.iter().find(|&&channel| channel >= available) {
+            return Err(SetupError::NoSuchChannel { channel, available });
+        }
+        let rate = source.sample_rate();
+        let mut stream = |channel| {
+            let stft = Stft::new(&frames, rate);
+            let main = stft.frame_len();
+            let mut lengths = frames
+                .shorter_millis
+                .iter()
+                .map(|&millis| (rate * millis as f64 / 1000.0) as usize)
+                .filter(|&len| len > 0 && len < main)
+                .collect::<Vec<_>>();
+            lengths.sort_unstable();
+            lengths.dedup();
+            lengths.push(main);
+            let resolutions = lengths
+                .into_iter()
+                .map(|len| Resolution {
+                    len,
+                    detector: detector(),
+                })
+                .collect();
+            Stream {
+                channel,
+                samples: default_vec(main),
+                stft,
+                resolutions,
+            }
+        };
+        let streams = if channels.separate {
+            selected.iter().map(|&channel| stream(Some(channel))).collect()
+        } else {
+            vec![stream(None)]
+        };
         Ok(Analyser {
-            fft: planner.plan_fft(bufsize),
-            fft_input: default_vec(bufsize),
-            fft_output: default_vec(bufsize),
-            alsa_source,
+            source,
+            selected,
+            streams,
+            next_stream: 0,
+            thresholds,
         })
     }
 }
 
-impl<'a, T> Analyser<'a, T> 
+impl<T, S> Analyser<T, S>
 where
-    T: FromAnySample + Num + Clone,
+    T: Float,
+    S: AudioSource<T>,
 {
-    pub fn read_data(&mut self) -> alsa::Result<()> {
-        self.alsa_source.read()
+    /// Read whatever the source has now, returning the number of frames read.
+    pub fn read_data(&mut self) -> Result<usize, S::Error> {
+        let num_channels = self.source.channels().max(1);
+        let fresh = self.source.read()?;
+        let frames = fresh.chunks_exact(num_channels);
+        let read = frames.len();
+        let selected = &self.selected;
+        let count = T::from(selected.len()).unwrap_or_else(T::one);
+        let mix = |frame: &[T]| {
+            selected.iter().fold(T::zero(), |acc, &channel| acc + frame[channel]) / count
+        };
+        for stream in self.streams.iter_mut() {
+            match stream.channel {
+                Some(channel) => stream.stft.feed(frames.clone().map(|f| f[channel])),
+                None => stream.stft.feed(frames.clone().map(mix)),
+            }
+        }
+        Ok(read)
     }
-}
 
-impl<'a, T> Analyser<'a, T> {
-    pub fn recover(&mut self, error: alsa::Error) -> alsa::Result<()> {
-        match error.errno() {
-            Some(Errno::EAGAIN) => Ok(()),
-            Some(Errno::EPIPE) => {
-                self.alsa_source.expand_input_buffer();
-                self.alsa_source.device.try_recover(error, true)
-            },
-            _ => Err(error),
+    /// Skip to the newest complete frame of every stream, for when the frames
+    /// come in faster than they are analysed. See `Stft::skip_to_latest`.
+    pub fn skip_to_latest(&mut self) {
+        for stream in self.streams.iter_mut() {
+            stream.stft.skip_to_latest();
         }
     }
-}
 
-impl<'a, T> Analyser<'a, T>
-where
-    T: FFTnum,
-{
-    pub fn do_fft(&mut self) {
-        self.alsa_source.clone_fft_data(&mut self.fft_input);
-        self.fft.process(&mut self.fft_input, &mut self.fft_output);
+    /// Try to carry on after a failed read, see `AudioSource::recover`.
+    pub fn recover(&mut self, error: S::Error) -> Result<(), S::Error> {
+        self.source.recover(error)
+    }
+
+    /// True once the source has run out. Frames made from what was already
+    /// read are still handed out by `next_analysis`.
+    pub fn is_finished(&self) -> bool {
+        self.source.is_finished()
+    }
+
+    /// See `AudioSource::poll_descriptors`.
+    pub fn poll_descriptors(&self) -> Result<Vec<pollfd>, S::Error> {
+        self.source.poll_descriptors()
+    }
+
+    /// See `AudioSource::poll_ready`.
+    pub fn poll_ready(&self, descriptors: &[pollfd]) -> Result<bool, S::Error> {
+        self.source.poll_ready(descriptors)
+    }
+
+    /// What audio the source lost so far, see `AudioSource::xruns`.
+    pub fn xruns(&self) -> Option<Xruns> {
+        self.source.xruns()
+    }
+
+    /// Estimate the pitch of the next frame, if enough data was read for it.
+    /// With separate channels, the channels take turns.
+    ///
+    /// Quiet signals are not even passed to the detector, and estimates it is
+    /// not confident enough about are dropped.
+    pub fn next_analysis(&mut self) -> Option<Analysis> {
+        let rate = self.source.sample_rate();
+        let count = self.streams.len();
+        for offset in 0..count {
+            let index = (self.next_stream + offset) % count;
+            if let Some(analysis) = self.streams[index].analyse(rate, &self.thresholds) {
+                self.next_stream = (index + 1) % count;
+                return Some(analysis);
+            }
+        }
+        None
     }
 }
 
-impl<'a, T> Analyser<'a, T>
-where
-    T: Float,
-{
-    pub fn dominant_frequency(&self) -> Option<f64> {
-        let index = self.fft_output.iter()
-            .map(Complex::norm)
-            .map(NotNan::new)
-            .enumerate()
-            .take(self.fft_output.len() / 2)
-            .skip(1)
-            .flat_map(|(i, norm)| norm.map(|nonnan| (i, nonnan)))
-            .max_by_key(|(_, norm)| *norm)
-            .map(|(i, _)| i);
-        index.map(|i| self.alsa_source.frequency_at(i))
+impl<T: Float> Stream<T> {
+    fn analyse(&mut self, sample_rate: f64, thresholds: &Thresholds) -> Option<Analysis> {
+        let time = self.stft.next_frame(&mut self.samples)?;
+        let main = self.samples.len();
+        for resolution in self.resolutions.iter_mut() {
+            let len = resolution.len;
+            let samples = &self.samples[main - len..];
+            let level = level(samples);
+            let estimate = if level >= thresholds.min_level {
+                resolution
+                    .detector
+                    .detect(samples, sample_rate)
+                    .filter(|e| e.confidence >= thresholds.min_confidence)
+            } else {
+                None
+            };
+            let lowest = MIN_PERIODS * sample_rate / len as f64;
+            let fits = estimate.is_some_and(|e| e.frequency >= lowest);
+            if fits || len == main {
+                let start = time.end - len as f64 / sample_rate;
+                return Some(Analysis {
+                    channel: self.channel,
+                    time: if len == main { time } else { Timestamp { start, ..time } },
+                    level,
+                    estimate,
+                });
+            }
+        }
+        None
     }
 }
 
 /* ---------- helpers ---------- */
 
-fn default_vec<T: Default>(size: usize) -> Vec<T> {
+/// RMS level of the samples, in dB, with the mean taken out so that any DC
+/// offset does not count.
+fn level<T: Float>(samples: &[T]) -> f64 {
+    let values = || samples.iter().flat_map(|s| s.to_f64());
+    let count = samples.len().max(1) as f64;
+    let mean = values().sum::<f64>() / count;
+    let power = values().map(|s| (s - mean) * (s - mean)).sum::<f64>() / count;
+    10.0 * power.log10()
+}
+
+/// A vector of `size` default values, for buffers of types that are not
+/// `Clone`.
+pub(crate) fn default_vec<T: Default>(size: usize) -> Vec<T> {
     let mut res = Vec::new();
     res.resize_with(size, T::default);
     res
 }
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use crate::detector::{DetectorSettings, Estimator};
+    use crate::generator::{Generator, SignalSettings, Waveform};
+    use crate::note::{Accidental, Note, Octave, Reading};
+    use crate::source::IterSource;
+    use crate::stabiliser::{Stabiliser, StabiliserSettings};
+    use crate::window::WindowKind;
+
+    const RATE: f64 = 44100.0;
+
+    fn detector(estimator: Estimator) -> DetectorSettings {
+        DetectorSettings {
+            estimator,
+            window: WindowKind::Hann,
+            harmonics: 5,
+            fft_size: None,
+        }
+    }
+
+    fn frames(length_millis: usize, hop_millis: usize) -> FrameSettings {
+        FrameSettings {
+            length_millis,
+            hop_millis,
+            shorter_millis: Vec::new(),
+        }
+    }
+
+    fn thresholds() -> Thresholds {
+        Thresholds {
+            min_level: -60.0,
+            min_confidence: 0.5,
+        }
+    }
+
+    /// Everything the analyser makes of the whole of `source`.
+    fn analyse_all<S>(
+        source: S,
+        frames: FrameSettings,
+        detector: DetectorSettings,
+    ) -> Vec<Analysis>
+    where
+        S: AudioSource<f64>,
+    {
+        let channels = ChannelSettings::default();
+        let mut analyser =
+            Analyser::new(source, frames, || detector.build(), channels, thresholds())
+                .expect("a mono source has a channel 1");
+        let mut res = Vec::new();
+        while !analyser.is_finished() {
+            analyser.read_data().unwrap_or_else(|_| panic!("reading failed"));
+            while let Some(analysis) = analyser.next_analysis() {
+                res.push(analysis);
+            }
+        }
+        res
+    }
+
+    fn cents(estimate: Option<Estimate>, frequency: f64) -> f64 {
+        let estimate = estimate.expect("no estimate");
+        1200.0 * (estimate.frequency / frequency).log2()
+    }
+
+    #[test]
+    fn frames_from_an_iterator() {
+        let frequency = 440.27;
+        let samples = (0..RATE as usize)
+            .map(|i| 0.5 * (2.0 * PI * frequency * i as f64 / RATE).sin())
+            .collect::<Vec<_>>();
+        let source = IterSource::new(samples, RATE);
+        let analyses = analyse_all(source, frames(100, 50), detector(Estimator::McLeod));
+        // The first frame is complete after 100 ms, then one every 50 ms.
+        assert_eq!(analyses.len(), 19);
+        for (i, analysis) in analyses.iter().enumerate() {
+            assert_eq!(analysis.channel, None);
+            let end = 0.1 + 0.05 * i as f64;
+            assert!((analysis.time.end - end).abs() < 1.0 / RATE);
+            assert!((analysis.time.start - (end - 0.1)).abs() < 1.0 / RATE);
+            // A sine at half the full scale.
+            assert!((analysis.level - -9.03).abs() < 0.1, "level {}", analysis.level);
+            assert!(cents(analysis.estimate, frequency).abs() < 1.0);
+        }
+    }
+
+    #[test]
+    fn notes_from_the_generator() {
+        for &estimator in Estimator::ALL.iter() {
+            let mut signal = SignalSettings::new(Waveform::Sawtooth, 110.0);
+            signal.detune = 15.0;
+            signal.amplitude = 0.3;
+            signal.noise = 0.01;
+            signal.duration = Some(2.5);
+            let source = IterSource::new(Generator::new(signal, RATE), RATE);
+            let frames = frames(estimator.default_millis(), 250);
+            let analyses = analyse_all(source, frames, detector(estimator));
+            assert!(!analyses.is_empty(), "{}: no frames", estimator.name());
+            for analysis in analyses.iter() {
+                let estimate = analysis.estimate.expect("no estimate");
+                let reading = Reading::from_frequency(estimate.frequency, 440.0)
+                    .expect("a positive frequency");
+                assert_eq!(reading.position.octave, Octave(2));
+                assert_eq!(reading.position.note, Note::A);
+                let (name, cents) = (estimator.name(), reading.cents);
+                assert!((cents - 15.0).abs() < 1.0, "{}: {} cents", name, cents);
+            }
+        }
+    }
+
+    /// A noisy, detuned note through the analyser and the stabiliser, with
+    /// the default frames and the stabiliser defaults of the command line,
+    /// shows the same note the whole time, and its cents hardly move.
+    #[test]
+    fn steady_reading_of_a_detuned_note() {
+        for &estimator in Estimator::ALL.iter() {
+            let waveform = Waveform::Harmonics {
+                count: 6,
+                inharmonicity: 0.0,
+            };
+            let mut signal = SignalSettings::new(waveform, 110.0);
+            signal.detune = 42.0;
+            signal.amplitude = 0.3;
+            signal.noise = 0.05;
+            signal.duration = Some(2.5);
+            let source = IterSource::new(Generator::new(signal, RATE), RATE);
+            let hop = estimator.default_hop_millis();
+            let frames = FrameSettings {
+                length_millis: estimator.default_millis(),
+                hop_millis: hop,
+                shorter_millis: estimator.default_resolutions().to_vec(),
+            };
+            let mut stabiliser = Stabiliser::new(StabiliserSettings {
+                median_len: 150 / hop,
+                smoothing: 0.3,
+                hysteresis: 15.0,
+                hold: 200 / hop,
+            });
+            let analyses = analyse_all(source, frames, detector(estimator));
+            let name = estimator.name();
+            assert!(analyses.len() > 10, "{}: {} frames", name, analyses.len());
+            let mut raw = Vec::new();
+            let mut shown = Vec::new();
+            for analysis in analyses.iter() {
+                raw.push(cents(analysis.estimate, 110.0));
+                let (reading, _) = stabiliser
+                    .update(analysis.estimate, 440.0)
+                    .expect("nothing shown");
+                assert_eq!(reading.position.octave, Octave(2), "{}", name);
+                assert_eq!(reading.position.note, Note::A, "{}", name);
+                shown.push(reading.cents);
+            }
+            for &cents in shown.iter() {
+                assert!((cents - 42.0).abs() < 2.0, "{}: {} cents", name, cents);
+            }
+            // The stabiliser takes out most of the wobble from frame to frame.
+            let wobble = |values: &[f64]| {
+                let steps = values.windows(2).map(|pair| (pair[1] - pair[0]).abs());
+                steps.sum::<f64>() / (values.len() - 1) as f64
+            };
+            let (shown, raw) = (wobble(&shown), wobble(&raw));
+            assert!(shown <= 0.5 * raw, "{}: {} against {} cents", name, shown, raw);
+        }
+    }
+
+    /// A sawtooth of `note` in `octave` for `duration` seconds.
+    fn sawtooth(note: Note, octave: i32, duration: f64) -> Generator {
+        let frequency = Octave(octave).note_frequency(note, Accidental::Natural, 440.0);
+        let mut signal = SignalSettings::new(Waveform::Sawtooth, frequency);
+        signal.amplitude = 0.3;
+        signal.duration = Some(duration);
+        Generator::new(signal, RATE)
+    }
+
+    fn reads(analysis: &Analysis, note: Note, octave: i32) -> bool {
+        analysis.estimate
+            .and_then(|estimate| Reading::from_frequency(estimate.frequency, 440.0))
+            .is_some_and(|reading| {
+                reading.position.note == note && reading.position.octave == Octave(octave)
+            })
+    }
+
+    /// With the frames the spectral estimators get by default, a high note
+    /// shows up within the shortest frame of its onset, while a low one is
+    /// still taken from the whole main frame.
+    #[test]
+    fn high_notes_from_shorter_frames() {
+        let onset = 2.0;
+        let low = sawtooth(Note::E, 1, onset);
+        let high = sawtooth(Note::C, 6, 0.5);
+        let source = IterSource::new(low.chain(high), RATE);
+        let estimator = Estimator::MaxBin;
+        let frames = FrameSettings {
+            length_millis: estimator.default_millis(),
+            hop_millis: estimator.default_hop_millis(),
+            shorter_millis: estimator.default_resolutions().to_vec(),
+        };
+        let main = frames.length_millis as f64 / 1000.0;
+        let shortest = 0.05;
+        assert_eq!(frames.shorter_millis.first(), Some(&50));
+        let analyses = analyse_all(source, frames, detector(estimator));
+        let before = analyses.iter()
+            .filter(|analysis| analysis.time.end <= onset)
+            .collect::<Vec<_>>();
+        assert!(!before.is_empty());
+        for analysis in before {
+            assert!(reads(analysis, Note::E, 1), "{:?}", analysis);
+            let span = analysis.time.end - analysis.time.start;
+            assert!((span - main).abs() < 1.0 / RATE, "E1 from {} s", span);
+        }
+        let first_high = analyses.iter()
+            .find(|analysis| reads(analysis, Note::C, 6))
+            .expect("C6 is never heard");
+        let delay = first_high.time.end - onset;
+        assert!(delay <= shortest + 1.0 / RATE, "C6 heard {} s late", delay);
+    }
+
+    #[test]
+    fn skipping_to_the_latest_frame() {
+        let frequency = 440.27;
+        let samples = (0..RATE as usize)
+            .map(|i| 0.5 * (2.0 * PI * frequency * i as f64 / RATE).sin())
+            .collect::<Vec<_>>();
+        let source = IterSource::new(samples, RATE).with_chunk_len(RATE as usize / 2);
+        let detector = detector(Estimator::McLeod);
+        let mut analyser = Analyser::new(
+            source,
+            frames(100, 50),
+            || detector.build(),
+            ChannelSettings::default(),
+            thresholds(),
+        )
+        .expect("a mono source has a channel 1");
+        let mut ends = Vec::new();
+        while !analyser.is_finished() {
+            analyser.read_data().unwrap_or_else(|_| panic!("reading failed"));
+            analyser.skip_to_latest();
+            while let Some(analysis) = analyser.next_analysis() {
+                assert!(cents(analysis.estimate, frequency).abs() < 1.0);
+                ends.push(analysis.time.end);
+            }
+        }
+        // Of the frames in each half a second, only the one ending with it.
+        assert_eq!(ends.len(), 2);
+        assert!((ends[0] - 0.5).abs() < 1.0 / RATE);
+        assert!((ends[1] - 1.0).abs() < 1.0 / RATE);
+    }
+
+    #[test]
+    fn quiet_signals_are_not_estimated() {
+        let mut signal = SignalSettings::new(Waveform::Sine, 440.0);
+        signal.amplitude = 1e-4;
+        signal.duration = Some(0.5);
+        let source = IterSource::new(Generator::new(signal, RATE), RATE);
+        let analyses = analyse_all(source, frames(100, 50), detector(Estimator::McLeod));
+        assert!(!analyses.is_empty());
+        for analysis in analyses.iter() {
+            assert!(analysis.level < -60.0);
+            assert_eq!(analysis.estimate, None);
+        }
+    }
+}
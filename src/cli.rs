@@ -1,14 +1,252 @@
+use alsa::pcm::{Access, Format, Frames};
+use structopt::clap::{self, ErrorKind};
 use structopt::StructOpt;
 
+use offkey::analyser::{ChannelSettings, Thresholds};
+use offkey::detector::{DetectorSettings, Estimator};
+use offkey::generator::SignalSettings;
+use offkey::stabiliser::StabiliserSettings;
+use offkey::stft::FrameSettings;
+use offkey::window::WindowKind;
+
+use crate::mic::{parse_format, MicSettings};
+use crate::table;
+
 #[derive(Debug, StructOpt)]
 pub struct CLIData {
     #[structopt(name = "device", short, long, default_value = "default")]
     pub device_name: String,
-    pub text_data_file: String,
+    /// Sample rate to ask the device for, in Hz [default: chosen by the
+    /// device]
+    #[structopt(long)]
+    pub rate: Option<u32>,
+    /// Sample format to ask the device for, as an ALSA name like S16_LE or
+    /// S24_3LE [default: chosen by the device]
+    #[structopt(long, parse(try_from_str = "parse_format"))]
+    pub format: Option<Format>,
+    /// Number of channels to ask the device for [default: chosen by the
+    /// device]
+    #[structopt(long = "capture-channels")]
+    pub capture_channels: Option<u32>,
+    /// Period size to ask the device for, in frames [default: chosen by the
+    /// device]
+    #[structopt(long)]
+    pub period: Option<usize>,
+    /// Buffer size to ask the device for, in frames [default: chosen by the
+    /// device]
+    #[structopt(long)]
+    pub buffer: Option<usize>,
+    /// WAV file to analyse instead of listening to the device
+    #[structopt(short, long)]
+    pub input: Option<String>,
+    /// Synthetic signal to analyse instead of listening to the device, as
+    /// WAVEFORM:FREQUENCY[,KEY=VALUE]..., for example
+    /// "harmonics:110,count=6,inharmonicity=0.0004,noise=0.05". Waveforms are
+    /// sine, saw, square and harmonics; keys are detune (cents), amplitude,
+    /// noise, attack and decay (seconds), duration (seconds), seed, and count
+    /// and inharmonicity for harmonics
+    #[structopt(long, raw(conflicts_with = "\"input\""))]
+    pub generate: Option<SignalSettings>,
+    /// Channels to analyse, counting from 1, like "1,3" [default: all]
+    #[structopt(
+        long,
+        raw(use_delimiter = "true", require_delimiter = "true"),
+        parse(try_from_str = "parse_channel")
+    )]
+    pub channels: Vec<usize>,
+    /// Analyse each channel on its own instead of mixing them together
+    #[structopt(long)]
+    pub separate: bool,
+    /// Remove any DC offset with a high-pass filter with this cutoff, in Hz,
+    /// which should be well below the lowest note
+    #[structopt(long = "dc-block")]
+    pub dc_block: Option<f64>,
+    /// Read the input file or the synthetic signal as fast as possible
+    /// rather than in real time
+    #[structopt(long)]
+    pub fast: bool,
+    /// Frequency of A in the First octave, in Hz
+    #[structopt(
+        short,
+        long,
+        default_value = "440",
+        parse(try_from_str = "parse_reference")
+    )]
+    pub reference: f64,
+    /// Window function applied before the FFT: rectangular, hann, hamming,
+    /// blackman-harris or flat-top
+    #[structopt(short, long, default_value = "hann")]
+    pub window: WindowKind,
+    /// How the fundamental is estimated: max-bin, hps (harmonic product
+    /// spectrum) or mpm (McLeod pitch method)
+    #[structopt(short, long, default_value = "max-bin")]
+    pub estimator: Estimator,
+    /// Number of harmonics used by the harmonic product spectrum
+    #[structopt(short = "H", long, default_value = "5")]
+    pub harmonics: usize,
+    /// Size of the FFT of the spectral estimators, in samples. Frames are
+    /// padded with zeroes up to it, or up to the next size the FFT is fast at
+    /// if they are longer [default: the next fast size]
+    #[structopt(long = "fft-size")]
+    pub fft_size: Option<usize>,
+    /// Length of the analysed frames, in milliseconds [default: 1750 for
+    /// the spectral estimators, 100 for mpm]
+    #[structopt(short, long)]
+    pub millis: Option<usize>,
+    /// Lengths of shorter frames analysed along with the main one, in
+    /// milliseconds, like "50,200", or 0 for none. A note is taken from the
+    /// shortest frame that spans enough of its periods, so high notes show up
+    /// sooner while low ones still get the whole frame. Pair it with a short
+    /// --hop [default: 50,200 for the spectral estimators, none for mpm]
+    #[structopt(
+        long,
+        raw(use_delimiter = "true", require_delimiter = "true")
+    )]
+    pub resolutions: Vec<usize>,
+    /// Time between the starts of consecutive frames, in milliseconds
+    /// [default: 25 for the spectral estimators, 50 for mpm]
+    #[structopt(long)]
+    pub hop: Option<usize>,
+    /// Signals quieter than this are considered silence, in dB
+    #[structopt(long = "min-level", default_value = "-60", allow_hyphen_values = true)]
+    pub min_level: f64,
+    /// Estimates less confident than this, from 0 to 1, are not shown
+    #[structopt(long = "min-confidence", default_value = "0.5")]
+    pub min_confidence: f64,
+    /// Stretch of time the median of the pitch is taken over, in
+    /// milliseconds. It covers at least one frame
+    #[structopt(long, default_value = "150")]
+    pub median: usize,
+    /// Weight of a new pitch in the exponential smoothing, from 0 to 1,
+    /// where 1 means no smoothing
+    #[structopt(long, default_value = "0.3")]
+    pub smoothing: f64,
+    /// How far past the half-way point to a neighbouring note the pitch has
+    /// to go before the note changes, in cents
+    #[structopt(long, default_value = "15")]
+    pub hysteresis: f64,
+    /// How long the last note is still shown once there is no clear pitch,
+    /// in milliseconds
+    #[structopt(long, default_value = "200")]
+    pub hold: usize,
+    /// File with the interface text, required unless a subcommand is given
+    pub text_data_file: Option<String>,
+    #[structopt(subcommand)]
+    pub command: Option<Subcommand>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Subcommand {
+    /// Analyse a WAV file and write the pitch of every frame as a table. The
+    /// analysis options go before the subcommand
+    #[structopt(name = "analyze")]
+    Analyze(AnalyzeData),
+    /// List the ALSA devices that can be captured from, with the sample
+    /// formats, channel counts and rates each one supports
+    #[structopt(name = "devices")]
+    Devices,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AnalyzeData {
+    /// Output format: csv or json
+    #[structopt(short, long, default_value = "csv")]
+    pub format: table::Format,
+    /// File to write the table to [default: standard output]
+    #[structopt(short, long)]
+    pub output: Option<String>,
+    /// WAV file to analyse
+    pub file: String,
 }
 
 impl CLIData {
     pub fn new() -> Self {
-        Self::from_args()
+        let res = Self::from_args();
+        if res.command.is_none() && res.text_data_file.is_none() {
+            clap::Error::with_description(
+                "the text data file is required unless a subcommand is given",
+                ErrorKind::MissingRequiredArgument,
+            )
+            .exit()
+        }
+        res
+    }
+
+    pub fn mic_settings(&self) -> MicSettings {
+        MicSettings {
+            format: self.format,
+            channels: self.capture_channels,
+            rate: self.rate,
+            access: Some(Access::RWInterleaved),
+            period_size: self.period.map(|size| size as Frames),
+            buffer_size: self.buffer.map(|size| size as Frames),
+        }
     }
+
+    pub fn detector(&self) -> DetectorSettings {
+        DetectorSettings {
+            estimator: self.estimator,
+            window: self.window,
+            harmonics: self.harmonics,
+            fft_size: self.fft_size,
+        }
+    }
+
+    pub fn frames(&self) -> FrameSettings {
+        let estimator = self.estimator;
+        let shorter_millis = if self.resolutions.is_empty() {
+            estimator.default_resolutions().to_vec()
+        } else {
+            self.resolutions.clone()
+        };
+        FrameSettings {
+            length_millis: self.millis.unwrap_or_else(|| estimator.default_millis()),
+            hop_millis: self.hop.unwrap_or_else(|| estimator.default_hop_millis()),
+            shorter_millis,
+        }
+    }
+
+    /// Stabiliser settings, with the times given in milliseconds turned into
+    /// numbers of frames.
+    pub fn stabiliser(&self) -> StabiliserSettings {
+        let hop = self.frames().hop_millis.max(1) as f64;
+        let frames = |millis: usize| (millis as f64 / hop).round() as usize;
+        StabiliserSettings {
+            median_len: frames(self.median).max(1),
+            smoothing: self.smoothing,
+            hysteresis: self.hysteresis,
+            hold: frames(self.hold),
+        }
+    }
+
+    pub fn channel_settings(&self) -> ChannelSettings {
+        ChannelSettings {
+            selected: self.channels.clone(),
+            separate: self.separate,
+        }
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            min_level: self.min_level,
+            min_confidence: self.min_confidence,
+        }
+    }
+}
+
+fn parse_reference(s: &str) -> Result<f64, String> {
+    let freq: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if freq.is_finite() && freq > 0.0 {
+        Ok(freq)
+    } else {
+        Err(format!("reference pitch must be positive, got {}", s))
+    }
+}
+
+/// Parse a channel number counting from 1 into one counting from 0.
+fn parse_channel(s: &str) -> Result<usize, String> {
+    let channel: usize = s.parse().map_err(|e| format!("{}", e))?;
+    channel
+        .checked_sub(1)
+        .ok_or_else(|| "channels are counted from 1".to_string())
 }
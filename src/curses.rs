@@ -3,9 +3,18 @@ use std::fmt;
 use pancurses_result::Input::Character;
 use pancurses_result::{initscr, Curses, Window};
 
-use crate::note::Position;
+use offkey::detector::Estimate;
+use offkey::note::Reading;
+use offkey::source::Xruns;
+use crate::mic::{format_name, Negotiated};
 use crate::text::Text;
 
+/// Largest number of characters on each side of the centre of the cents
+/// meter. It is narrower when there are several channels to show.
+const METER_HALF_WIDTH: i32 = 20;
+/// Deviation shown at the edges of the cents meter.
+const METER_RANGE_CENTS: f64 = 50.0;
+
 pub fn init_curses() -> Result<Curses, Error> {
     let mut res = initscr().map_err(|_| "failed to initialize ncurses library")?;
     res.window_mut()
@@ -14,33 +23,162 @@ pub fn init_curses() -> Result<Curses, Error> {
     Ok(res)
 }
 
+/// What is shown for one analysed channel, or for the mix of channels.
+#[derive(Debug, Clone, Copy)]
+pub struct Heard {
+    pub channel: Option<usize>,
+    pub reading: Option<(Reading, Estimate)>,
+    /// RMS level, in dB.
+    pub level: f64,
+}
+
+/// The device captured from, as shown on screen.
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub device: String,
+    pub negotiated: Negotiated,
+}
+
+/// Draw everything, with the channels side by side.
 pub fn draw_state(
     curses: &mut Curses,
     text: &Text,
-    position: Position,
-    _freq: f64,
+    heard: &[Heard],
+    reference: f64,
+    capture: Option<&Capture>,
+    xruns: Option<Xruns>,
 ) -> Result<(), Error> {
     let win = curses.window_mut();
     win.erase().map_err(|_| "failed to clear the window")?;
     win.draw_box('|', '-')
         .map_err(|_| "failed to draw borders")?;
     let (maxy, maxx) = win.size().into();
-    move_to(win, maxx / 2, maxy / 2)?;
+    let column_width = maxx / (heard.len().max(1) as i32);
+    for (i, heard) in heard.iter().enumerate() {
+        let left = i as i32 * column_width;
+        let centre = left + column_width / 2;
+        if let Some(channel) = heard.channel {
+            move_to(win, centre, maxy / 2 - 2)?;
+            printw(win, format_args!("{} {}", text.channel, channel + 1))?;
+        }
+        if let Some((reading, estimate)) = heard.reading {
+            let meter_half_width = METER_HALF_WIDTH.min(column_width / 2 - 1).max(1);
+            draw_reading(win, text, centre, meter_half_width, reading, estimate)?;
+        } else {
+            move_to(win, centre, maxy / 2)?;
+            print(win, &text.no_signal)?;
+        }
+        move_to(win, left + 2, maxy - 3)?;
+        let level = heard.level;
+        printw(win, format_args!("{}: {:.1} {}", text.level, level, text.decibels))?;
+    }
+    move_to(win, 2, maxy - 2)?;
+    printw(win, format_args!("{}: {:.1} {}", text.reference, reference, text.hertz))?;
+    let mut line = 1;
+    if let Some(capture) = capture {
+        let negotiated = capture.negotiated;
+        move_to(win, 2, line)?;
+        printw(
+            win,
+            format_args!(
+                "{}: {} ({}, {} {}, {} {})",
+                text.device,
+                capture.device,
+                format_name(negotiated.format).unwrap_or("?"),
+                negotiated.channels,
+                text.channels,
+                negotiated.rate,
+                text.hertz
+            ),
+        )?;
+        line += 1;
+    }
+    if let Some(xruns) = xruns {
+        move_to(win, 2, line)?;
+        printw(
+            win,
+            format_args!(
+                "{}: {}, {}: {}",
+                text.overruns, xruns.overruns, text.suspends, xruns.suspends
+            ),
+        )?;
+        move_to(win, 2, line + 1)?;
+        printw(
+            win,
+            format_args!(
+                "{}: {} {}, {}: {} {}",
+                text.period,
+                xruns.period_size,
+                text.frames,
+                text.buffer,
+                xruns.buffer_size,
+                text.frames
+            ),
+        )?;
+        move_to(win, 2, line + 2)?;
+        let dropped = xruns.dropped;
+        printw(win, format_args!("{}: {} {}", text.dropped, dropped, text.frames))?;
+    }
+    // Without waiting for a key, which would do it as well, the window has
+    // to be copied to the screen explicitly.
+    win.refresh_virtual_screen()
+        .map_err(|_| "failed to refresh the window")?;
+    curses.update().map_err(|_| "failed to update the screen")?;
+    Ok(())
+}
+
+fn draw_reading(
+    win: &mut Window,
+    text: &Text,
+    centre: i32,
+    meter_half_width: i32,
+    reading: Reading,
+    estimate: Estimate,
+) -> Result<(), Error> {
+    let position = reading.position;
+    let (maxy, _) = win.size().into();
+    move_to(win, centre, maxy / 2)?;
     print(win, text.octave_name(position.octave))?;
-    move_to(win, maxx / 2, maxy / 2 + 1)?;
+    move_to(win, centre, maxy / 2 + 1)?;
     let note = &text.notes[&position.note];
     let acc = &text.accidentals[&position.accidental];
     printw(win, format_args!("{} {}", note, acc))?;
-    curses.update().map_err(|_| "failed to update the screen")?;
+    move_to(win, centre, maxy / 2 + 2)?;
+    printw(win, format_args!("{:+.1} {}", reading.cents, text.cents))?;
+    move_to(win, centre, maxy / 2 + 3)?;
+    printw(
+        win,
+        format_args!(
+            "{:.2} / {:.2} {}",
+            estimate.frequency, reading.target_freq, text.hertz
+        ),
+    )?;
+    move_to(win, centre, maxy / 2 + 5)?;
+    printw(win, format_args!("{}: {:.2}", text.confidence, estimate.confidence))?;
+    move_to(win, centre - meter_half_width, maxy / 2 + 4)?;
+    print(win, &cents_meter(reading.cents, meter_half_width))?;
     Ok(())
 }
 
-pub fn is_done(curses: &mut Curses) -> bool {
-    let ch = curses.window_mut().read_char();
-    if let Some(Character('q')) = ch {
-        true
-    } else {
-        false
+/* ---------- input ---------- */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Quit,
+    RaiseReference,
+    LowerReference,
+}
+
+/// The next command typed, skipping other keys, or `None` when there are no
+/// more keys to read.
+pub fn read_command(curses: &mut Curses) -> Option<Command> {
+    loop {
+        match curses.window_mut().read_char()? {
+            Character('q') => return Some(Command::Quit),
+            Character('+') => return Some(Command::RaiseReference),
+            Character('-') => return Some(Command::LowerReference),
+            _ => (),
+        }
     }
 }
 
@@ -82,6 +220,24 @@ fn print(win: &mut Window, text: &str) -> Result<(), Error> {
     })
 }
 
+/// A horizontal bar with a marker showing how far off the note we are.
+fn cents_meter(cents: f64, half_width: i32) -> String {
+    let half = f64::from(half_width);
+    let offset = (cents / METER_RANGE_CENTS * half).round().clamp(-half, half);
+    let marker = half_width + offset as i32;
+    (0..=2 * half_width)
+        .map(|i| {
+            if i == marker {
+                '*'
+            } else if i == half_width {
+                '|'
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
 fn printw(win: &mut Window, args: fmt::Arguments) -> Result<(), Error> {
     let s = format!("{}", args);
     print(win, &s)
@@ -0,0 +1,132 @@
+use std::str::FromStr;
+
+use rustfft::num_traits::Float;
+use rustfft::FFTnum;
+
+use crate::mcleod::McLeod;
+use crate::spectral::{PeakPicking, Spectral};
+use crate::window::WindowKind;
+
+/* ---------- types ---------- */
+
+/// Something that can tell the pitch of a chunk of audio.
+///
+/// Detectors are expected to cope with chunks of any length, reallocating
+/// whatever they need when the length changes.
+pub trait PitchDetector<T> {
+    /// Estimate the pitch of normalised mono `samples`, taken at
+    /// `sample_rate` Hz.
+    fn detect(&mut self, samples: &[T], sample_rate: f64) -> Option<Estimate>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub frequency: f64,
+    /// How sure the detector is about the estimate, from 0 to 1.
+    pub confidence: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetectorSettings {
+    pub estimator: Estimator,
+    pub window: WindowKind,
+    /// Number of harmonics considered by the harmonic product estimator.
+    pub harmonics: usize,
+    /// Size of the FFT of the spectral estimators, in samples, see
+    /// `Spectral::with_fft_size`.
+    pub fft_size: Option<usize>,
+}
+
+/// How the fundamental frequency is estimated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Estimator {
+    /// The loudest bin of the spectrum.
+    MaxBin,
+    /// The bin with the largest product of magnitudes at its multiples, which
+    /// is not fooled by overtones louder than the fundamental.
+    HarmonicProduct,
+    /// McLeod Pitch Method, which works in the time domain and does not need
+    /// nearly as much data as the spectral estimators.
+    McLeod,
+}
+
+/* ---------- settings ---------- */
+
+impl DetectorSettings {
+    pub fn build<T: FFTnum + Float + Default>(&self) -> Box<dyn PitchDetector<T>> {
+        match self.estimator {
+            Estimator::MaxBin => {
+                let spectral = Spectral::new(self.window, PeakPicking::Loudest);
+                Box::new(spectral.with_fft_size(self.fft_size))
+            }
+            Estimator::HarmonicProduct => {
+                let picking = PeakPicking::HarmonicProduct {
+                    harmonics: self.harmonics.max(1),
+                };
+                let spectral = Spectral::new(self.window, picking);
+                Box::new(spectral.with_fft_size(self.fft_size))
+            }
+            Estimator::McLeod => Box::new(McLeod::new()),
+        }
+    }
+}
+
+/* ---------- estimators ---------- */
+
+impl Estimator {
+    pub const ALL: [Estimator; 3] =
+        [Estimator::MaxBin, Estimator::HarmonicProduct, Estimator::McLeod];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Estimator::MaxBin => "max-bin",
+            Estimator::HarmonicProduct => "hps",
+            Estimator::McLeod => "mpm",
+        }
+    }
+
+    /// How much audio the estimator needs to resolve the lowest notes.
+    pub fn default_millis(self) -> usize {
+        match self {
+            Estimator::MaxBin | Estimator::HarmonicProduct => 1750,
+            Estimator::McLeod => 100,
+        }
+    }
+
+    /// Lengths of the shorter frames analysed along with the main one, in
+    /// milliseconds. Without them, the long frames of the spectral estimators
+    /// would hold back high notes, which need only a fraction of the frame.
+    pub fn default_resolutions(self) -> &'static [usize] {
+        match self {
+            Estimator::MaxBin | Estimator::HarmonicProduct => &[50, 200],
+            Estimator::McLeod => &[],
+        }
+    }
+
+    /// Time between the starts of consecutive frames, in milliseconds. The
+    /// shorter frames of the spectral estimators only help when the frames
+    /// come often, but each one costs an FFT of the main frame, so this is
+    /// as often as four channels analysed separately keep up with real time.
+    pub fn default_hop_millis(self) -> usize {
+        match self {
+            Estimator::MaxBin | Estimator::HarmonicProduct => 25,
+            Estimator::McLeod => 50,
+        }
+    }
+}
+
+impl FromStr for Estimator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Estimator::ALL
+            .iter()
+            .find(|estimator| estimator.name() == s)
+            .cloned()
+            .ok_or_else(|| {
+                let names = Estimator::ALL.iter().map(|estimator| estimator.name());
+                let names = names.collect::<Vec<_>>().join(", ");
+                format!("unknown estimator '{}', expected one of: {}", s, names)
+            })
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::{self, Write};
+
+use alsa::device_name::HintIter;
+use alsa::pcm::{Format, HwParams};
+use alsa::{Direction, ValueOr, PCM};
+
+use crate::mic::format_name;
+
+/* ---------- types ---------- */
+
+/// A PCM that can be captured from, as listed in the ALSA configuration.
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// What a device can be asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    /// Formats the tuner can read, in the order ALSA lists them.
+    pub formats: Vec<Format>,
+    pub channels: (u32, u32),
+    pub rates: (u32, u32),
+}
+
+/* ---------- main things ---------- */
+
+/// All PCMs that are not playback only.
+pub fn capture_devices() -> alsa::Result<Vec<Device>> {
+    let hints = HintIter::new_str(None, "pcm")?;
+    let devices = hints
+        .filter(|hint| hint.direction != Some(Direction::Playback))
+        .filter_map(|hint| {
+            let description = hint.desc.map(|desc| {
+                // Descriptions tend to be split over a couple of lines.
+                desc.lines().map(str::trim).collect::<Vec<_>>().join(", ")
+            });
+            Some(Device {
+                name: hint.name?,
+                description,
+            })
+        });
+    Ok(devices.collect())
+}
+
+impl Capabilities {
+    /// Open a device for a moment to find out what it supports.
+    pub fn probe(device: &str) -> alsa::Result<Self> {
+        let pcm = PCM::new(device, Direction::Capture, true)?;
+        let params = HwParams::any(&pcm)?;
+        // Every probe starts from a fresh copy, as a successful one narrows
+        // down what the others can choose from.
+        let formats = Format::all()
+            .iter()
+            .cloned()
+            .filter(|&format| format_name(format).is_some())
+            .filter(|&format| params.clone().set_format(format).is_ok())
+            .collect();
+        let channels = (
+            params.clone().set_channels_near(1)?,
+            params.clone().set_channels_near(u32::MAX)?,
+        );
+        let rates = (
+            params.clone().set_rate_near(1, ValueOr::Nearest)?,
+            params.clone().set_rate_near(u32::MAX, ValueOr::Nearest)?,
+        );
+        Ok(Capabilities {
+            formats,
+            channels,
+            rates,
+        })
+    }
+}
+
+/// Write a device with what it supports, or why it could not be probed.
+pub fn write_device<W: Write>(
+    out: &mut W,
+    device: &Device,
+    capabilities: alsa::Result<Capabilities>,
+) -> io::Result<()> {
+    writeln!(out, "{}", device.name)?;
+    if let Some(description) = &device.description {
+        writeln!(out, "    {}", description)?;
+    }
+    match capabilities {
+        Ok(caps) => {
+            let formats = caps.formats.iter().flat_map(|&format| format_name(format));
+            let formats = formats.collect::<Vec<_>>();
+            if formats.is_empty() {
+                writeln!(out, "    formats: none supported")?;
+            } else {
+                writeln!(out, "    formats: {}", formats.join(", "))?;
+            }
+            writeln!(out, "    channels: {}", range(caps.channels))?;
+            writeln!(out, "    rates: {} Hz", range(caps.rates))?;
+        }
+        Err(error) => writeln!(out, "    could not be opened: {}", error)?,
+    }
+    Ok(())
+}
+
+/* ---------- helpers ---------- */
+
+fn range((min, max): (u32, u32)) -> String {
+    if min == max {
+        min.to_string()
+    } else {
+        format!("{}-{}", min, max)
+    }
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The null plugin takes anything, so every format the tuner reads shows
+    /// up.
+    #[test]
+    fn probe_null() {
+        let caps = Capabilities::probe("null").expect("the null plugin opens");
+        assert!(caps.formats.contains(&Format::S16LE));
+        assert!(caps.formats.contains(&Format::S243LE));
+        assert!(caps.formats.contains(&Format::FloatLE));
+        assert!(caps.formats.iter().all(|&format| format_name(format).is_some()));
+        assert_eq!(caps.channels.0, 1);
+        assert!(caps.channels.1 >= 2);
+        assert!(caps.rates.0 <= 8000 && caps.rates.1 >= 192_000);
+    }
+
+    #[test]
+    fn null_is_listed() {
+        let devices = capture_devices().expect("the hints can be read");
+        assert!(devices.iter().any(|device| device.name == "null"));
+    }
+
+    #[test]
+    fn write_null() {
+        let device = Device {
+            name: "null".into(),
+            description: Some("Nothing at all".into()),
+        };
+        let caps = Capabilities {
+            formats: vec![Format::S16LE, Format::S243LE],
+            channels: (1, 2),
+            rates: (44100, 44100),
+        };
+        let mut out = Vec::new();
+        write_device(&mut out, &device, Ok(caps)).expect("writing to a vector");
+        let expected = "null\n    Nothing at all\n    formats: S16_LE, S24_3LE\n    \
+                        channels: 1-2\n    rates: 44100 Hz\n";
+        assert_eq!(String::from_utf8(out).expect("text"), expected);
+    }
+}
@@ -1,7 +1,10 @@
+use std::error::Error as StdError;
 use std::io;
 
 use snafu::Snafu;
 
+use offkey::{analyser, wav};
+
 use crate::curses;
 use crate::text;
 
@@ -10,10 +13,24 @@ use crate::text;
 pub enum Error {
     #[snafu(display("ALSA error while setting up the input device: {}", source))]
     AlsaDeviceSetup { source: alsa::Error },
+    #[snafu(display("Failed to list ALSA devices: {}", source))]
+    DeviceListing { source: alsa::Error },
     #[snafu(display("ALSA error while creaing an analyser: {}", source))]
     AnalyserSetup { source: alsa::Error },
-    #[snafu(display("ALSA error while processing: {}", source))]
-    AlsaProcessing { source: alsa::Error },
+    #[snafu(display("Failed to start capturing: {}", source))]
+    CaptureThread { source: io::Error },
+    #[snafu(display("Failed to open the input file: {}", source))]
+    InputFileOpen { source: io::Error },
+    #[snafu(display("Failed to read the input file: {}", source))]
+    InputFileSetup { source: wav::Error },
+    #[snafu(display("Invalid channel selection: {}", source))]
+    ChannelSelection { source: analyser::SetupError },
+    #[snafu(display("Failed to wait for input: {}", source))]
+    Poll { source: alsa::Error },
+    #[snafu(display("Error while processing: {}", source))]
+    Processing { source: Box<dyn StdError> },
+    #[snafu(display("Failed to write the output: {}", source))]
+    OutputWrite { source: io::Error },
     #[snafu(display("Failed to read text data: {}", source))]
     TextFileRead { source: io::Error },
     #[snafu(display("Failed to deserialize text data: {}", source))]
@@ -0,0 +1,158 @@
+use std::f64::consts::PI;
+
+use libc::pollfd;
+use rustfft::num_traits::Float;
+
+use crate::source::{AudioSource, Xruns};
+
+/* ---------- types ---------- */
+
+/// A source with any DC offset taken out of its audio by a first-order
+/// high-pass filter, y[n] = x[n] - x[n-1] + r y[n-1].
+///
+/// The cutoff should be well below the lowest note of interest, as anything
+/// near it is weakened as well.
+pub struct DcBlocker<S, T> {
+    source: S,
+    /// The pole of the filter, just below 1.
+    pole: T,
+    /// The last input and output sample of each channel, empty until the
+    /// first frame is read.
+    last_in: Vec<T>,
+    last_out: Vec<T>,
+    channels: usize,
+    /// Samples from the latest read.
+    data: Vec<T>,
+}
+
+/* ---------- main things ---------- */
+
+impl<S, T> DcBlocker<S, T>
+where
+    S: AudioSource<T>,
+    T: Float,
+{
+    /// Filter the audio from `source`, with the cutoff in Hz.
+    pub fn new(source: S, cutoff: f64) -> Self {
+        let pole = (-2.0 * PI * cutoff.max(0.0) / source.sample_rate()).exp();
+        DcBlocker {
+            pole: T::from(pole).unwrap_or_else(T::one),
+            last_in: Vec::new(),
+            last_out: Vec::new(),
+            channels: source.channels().max(1),
+            data: Vec::new(),
+            source,
+        }
+    }
+}
+
+impl<S, T> AudioSource<T> for DcBlocker<S, T>
+where
+    S: AudioSource<T>,
+    T: Float,
+{
+    type Error = S::Error;
+
+    fn sample_rate(&self) -> f64 {
+        self.source.sample_rate()
+    }
+
+    fn channels(&self) -> usize {
+        self.source.channels()
+    }
+
+    fn read(&mut self) -> Result<&[T], Self::Error> {
+        let fresh = self.source.read()?;
+        self.data.clear();
+        for frame in fresh.chunks_exact(self.channels) {
+            if self.last_in.is_empty() {
+                // Starting from the first frame rather than from silence
+                // avoids a step at the start.
+                self.last_in = frame.to_vec();
+                self.last_out = vec![T::zero(); self.channels];
+            }
+            for (ch, &sample) in frame.iter().enumerate() {
+                let out = sample - self.last_in[ch] + self.pole * self.last_out[ch];
+                self.last_in[ch] = sample;
+                self.last_out[ch] = out;
+                self.data.push(out);
+            }
+        }
+        Ok(&self.data)
+    }
+
+    fn recover(&mut self, error: Self::Error) -> Result<(), Self::Error> {
+        self.source.recover(error)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.source.is_finished()
+    }
+
+    fn poll_descriptors(&self) -> Result<Vec<pollfd>, Self::Error> {
+        self.source.poll_descriptors()
+    }
+
+    fn poll_ready(&self, descriptors: &[pollfd]) -> Result<bool, Self::Error> {
+        self.source.poll_ready(descriptors)
+    }
+
+    fn xruns(&self) -> Option<Xruns> {
+        self.source.xruns()
+    }
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::IterSource;
+
+    const RATE: f64 = 44100.0;
+
+    /// Silence, then a sine of 0.3 at 440 Hz on top of an offset of 0.5,
+    /// through a DC blocker with a cutoff of 5 Hz.
+    fn filtered(silence: f64, duration: f64) -> Vec<f64> {
+        let silence = (silence * RATE) as usize;
+        let samples = (0..(duration * RATE) as usize)
+            .map(|i| 0.5 + 0.3 * (2.0 * PI * 440.0 * i as f64 / RATE).sin());
+        let samples = std::iter::repeat_n(0.0, silence).chain(samples);
+        let mut blocker = DcBlocker::new(IterSource::new(samples, RATE), 5.0);
+        let mut res = Vec::new();
+        while !blocker.is_finished() {
+            match blocker.read() {
+                Ok(chunk) => res.extend_from_slice(chunk),
+                Err(error) => match error {},
+            }
+        }
+        res
+    }
+
+    fn mean(samples: &[f64]) -> f64 {
+        samples.iter().sum::<f64>() / samples.len() as f64
+    }
+
+    #[test]
+    fn dc_offset_decays() {
+        let out = filtered(0.1, 1.0);
+        let onset = (0.1 * RATE) as usize;
+        // The offset comes through at first, being a step.
+        let period = (RATE / 440.0) as usize;
+        assert!(mean(&out[onset..onset + period]) > 0.45);
+        // A second later, 0.2 s of it, a whole number of periods, averages
+        // out to nothing, while the sine keeps its power.
+        let last = &out[out.len() - (0.2 * RATE) as usize..];
+        assert!(mean(last).abs() < 1e-4, "mean {}", mean(last));
+        let rms = mean(&last.iter().map(|s| s * s).collect::<Vec<_>>()).sqrt();
+        let expected = 0.3 / 2f64.sqrt();
+        assert!((rms / expected - 1.0).abs() < 0.01, "rms {}", rms);
+    }
+
+    #[test]
+    fn an_offset_from_the_start_is_not_a_step() {
+        let out = filtered(0.0, 0.2);
+        let period = (RATE / 440.0) as usize;
+        assert!(mean(&out[..period]).abs() < 0.05, "mean {}", mean(&out[..period]));
+    }
+}
@@ -0,0 +1,361 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use crate::note::CENTS_PER_SEMITONE;
+
+/* ---------- constants ---------- */
+
+/// Partials above this number are not generated, to keep the cost down.
+const MAX_PARTIALS: usize = 64;
+const DEFAULT_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/* ---------- types ---------- */
+
+/// An endless (or not) stream of synthetic mono samples, meant for testing.
+///
+/// Sawtooth and square waves are built from their partials and are band
+/// limited, so they do not alias.
+pub struct Generator {
+    settings: SignalSettings,
+    sample_rate: f64,
+    /// Frequency and amplitude of each partial.
+    partials: Vec<(f64, f64)>,
+    /// Phase of each partial, in cycles.
+    phases: Vec<f64>,
+    noise: Noise,
+    /// Number of samples produced so far.
+    position: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SignalSettings {
+    pub waveform: Waveform,
+    /// Frequency of the fundamental, in Hz, before detuning.
+    pub frequency: f64,
+    /// How far the whole signal is shifted from `frequency`, in cents.
+    pub detune: f64,
+    /// Peak amplitude of a sine with the same power as the signal, from 0 to
+    /// 1.
+    pub amplitude: f64,
+    /// Peak amplitude of the white noise added to the signal.
+    pub noise: f64,
+    pub envelope: Envelope,
+    /// Length of the signal in seconds, or `None` for an endless one.
+    pub duration: Option<f64>,
+    /// Seed of the noise generator.
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Sawtooth,
+    Square,
+    /// A number of harmonics with amplitudes falling as 1/n. The frequency of
+    /// the n-th one is stretched by a factor of sqrt(1 + B n^2), like in a
+    /// stiff string, where B is the inharmonicity.
+    Harmonics { count: usize, inharmonicity: f64 },
+}
+
+/// Amplitude envelope: a linear rise followed by an exponential decay.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Envelope {
+    /// Time to reach the full amplitude, in seconds.
+    pub attack: f64,
+    /// Time for the amplitude to fall by a factor of e after the attack, in
+    /// seconds, or 0 for no decay.
+    pub decay: f64,
+}
+
+/// A xorshift64* pseudorandom generator, good enough for noise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Noise {
+    state: u64,
+}
+
+/* ---------- main things ---------- */
+
+impl Generator {
+    pub fn new(settings: SignalSettings, sample_rate: f64) -> Self {
+        let fundamental = settings.frequency
+            * (settings.detune / (12.0 * CENTS_PER_SEMITONE)).exp2();
+        let nyquist = sample_rate / 2.0;
+        let mut partials = settings
+            .waveform
+            .partials(fundamental)
+            .take_while(|&(freq, _)| freq < nyquist)
+            .take(MAX_PARTIALS)
+            .collect::<Vec<_>>();
+        // Scale so that the power is that of a sine with the given amplitude.
+        let power = partials.iter().map(|(_, amp)| amp * amp).sum::<f64>();
+        if power > 0.0 {
+            let gain = settings.amplitude / power.sqrt();
+            for (_, amp) in partials.iter_mut() {
+                *amp *= gain;
+            }
+        }
+        Generator {
+            settings,
+            sample_rate,
+            phases: vec![0.0; partials.len()],
+            partials,
+            noise: Noise::new(settings.seed),
+            position: 0,
+        }
+    }
+
+    fn envelope(&self, time: f64) -> f64 {
+        let Envelope { attack, decay } = self.settings.envelope;
+        if time < attack {
+            time / attack
+        } else if decay > 0.0 {
+            (-(time - attack) / decay).exp()
+        } else {
+            1.0
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<f64> {
+        let time = self.position as f64 / self.sample_rate;
+        if self.settings.duration.is_some_and(|duration| time >= duration) {
+            return None;
+        }
+        self.position += 1;
+        let mut value = 0.0;
+        for (&(freq, amp), phase) in self.partials.iter().zip(self.phases.iter_mut()) {
+            value += amp * (2.0 * PI * *phase).sin();
+            *phase = (*phase + freq / self.sample_rate).fract();
+        }
+        Some(value * self.envelope(time) + self.settings.noise * self.noise.next_f64())
+    }
+}
+
+/* ---------- settings ---------- */
+
+impl SignalSettings {
+    /// A steady signal at full scale, without noise.
+    pub fn new(waveform: Waveform, frequency: f64) -> Self {
+        SignalSettings {
+            waveform,
+            frequency,
+            detune: 0.0,
+            amplitude: 1.0,
+            noise: 0.0,
+            envelope: Envelope::default(),
+            duration: None,
+            seed: DEFAULT_SEED,
+        }
+    }
+}
+
+/// Parses `WAVEFORM:FREQUENCY[,KEY=VALUE]...`, like `saw:110,detune=-20`.
+///
+/// The waveform is one of `sine`, `saw`, `square` or `harmonics`. The keys
+/// are `detune`, `amplitude`, `noise`, `attack`, `decay`, `duration` and
+/// `seed`, plus `count` and `inharmonicity` for harmonics.
+impl FromStr for SignalSettings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let head = parts.next().unwrap_or("");
+        let (waveform, frequency) = head
+            .split_once(':')
+            .ok_or_else(|| format!("expected WAVEFORM:FREQUENCY, got '{}'", head))?;
+        let frequency = parse_number(frequency)?;
+        let mut count = 8;
+        let mut inharmonicity = 0.0;
+        let mut res = SignalSettings::new(Waveform::Sine, frequency);
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", part))?;
+            match key {
+                "detune" => res.detune = parse_number(value)?,
+                "amplitude" => res.amplitude = parse_number(value)?,
+                "noise" => res.noise = parse_number(value)?,
+                "attack" => res.envelope.attack = parse_number(value)?,
+                "decay" => res.envelope.decay = parse_number(value)?,
+                "duration" => res.duration = Some(parse_number(value)?),
+                "seed" => res.seed = value.parse().map_err(|e| format!("{}", e))?,
+                "count" => count = value.parse().map_err(|e| format!("{}", e))?,
+                "inharmonicity" => inharmonicity = parse_number(value)?,
+                _ => return Err(format!("unknown signal parameter '{}'", key)),
+            }
+        }
+        res.waveform = match waveform {
+            "sine" => Waveform::Sine,
+            "saw" => Waveform::Sawtooth,
+            "square" => Waveform::Square,
+            "harmonics" => Waveform::Harmonics {
+                count,
+                inharmonicity,
+            },
+            _ => {
+                let names = "sine, saw, square, harmonics";
+                return Err(format!(
+                    "unknown waveform '{}', expected one of: {}",
+                    waveform, names
+                ));
+            }
+        };
+        if !res.frequency.is_finite() || res.frequency <= 0.0 {
+            return Err(format!("frequency must be positive, got {}", res.frequency));
+        }
+        Ok(res)
+    }
+}
+
+/* ---------- waveforms ---------- */
+
+impl Waveform {
+    /// Frequencies and relative amplitudes of the partials, lowest first.
+    fn partials(self, fundamental: f64) -> impl Iterator<Item = (f64, f64)> {
+        let count = match self {
+            Waveform::Sine => 1,
+            Waveform::Harmonics { count, .. } => count,
+            Waveform::Sawtooth | Waveform::Square => usize::MAX,
+        };
+        (1..=count).filter_map(move |n| {
+            let harmonic = n as f64;
+            let stretch = match self {
+                Waveform::Square if n % 2 == 0 => return None,
+                Waveform::Harmonics { inharmonicity, .. } => {
+                    (1.0 + inharmonicity * harmonic * harmonic).sqrt()
+                }
+                _ => 1.0,
+            };
+            Some((harmonic * fundamental * stretch, 1.0 / harmonic))
+        })
+    }
+}
+
+/* ---------- helpers ---------- */
+
+impl Noise {
+    fn new(seed: u64) -> Self {
+        // The state must never be zero.
+        Noise { state: seed.max(1) }
+    }
+
+    /// A uniformly distributed number in [-1, 1).
+    fn next_f64(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        // The top 53 bits make a double in [0, 1).
+        (value >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    }
+}
+
+fn parse_number(s: &str) -> Result<f64, String> {
+    s.parse::<f64>().map_err(|e| format!("'{}': {}", s, e))
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f64 = 44100.0;
+
+    fn rms(samples: &[f64]) -> f64 {
+        let power = samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64;
+        power.sqrt()
+    }
+
+    #[test]
+    fn parses_signal_settings() {
+        let settings: SignalSettings =
+            "harmonics:110,count=6,inharmonicity=0.0004,noise=0.05,detune=-20,duration=2"
+                .parse()
+                .expect("valid settings");
+        assert_eq!(
+            settings.waveform,
+            Waveform::Harmonics {
+                count: 6,
+                inharmonicity: 0.0004
+            }
+        );
+        assert_eq!(settings.frequency, 110.0);
+        assert_eq!(settings.noise, 0.05);
+        assert_eq!(settings.detune, -20.0);
+        assert_eq!(settings.duration, Some(2.0));
+        assert!("flute:440".parse::<SignalSettings>().is_err());
+        assert!("sine:440,colour=red".parse::<SignalSettings>().is_err());
+        assert!("sine:-440".parse::<SignalSettings>().is_err());
+        assert!("sine".parse::<SignalSettings>().is_err());
+    }
+
+    #[test]
+    fn duration_limits_the_signal() {
+        let mut settings = SignalSettings::new(Waveform::Sine, 440.0);
+        settings.duration = Some(0.5);
+        assert_eq!(Generator::new(settings, RATE).count(), 22050);
+    }
+
+    #[test]
+    fn partials_stay_below_nyquist() {
+        let settings = SignalSettings::new(Waveform::Sawtooth, 1000.0);
+        let generator = Generator::new(settings, RATE);
+        assert_eq!(generator.partials.len(), 22);
+        let settings = SignalSettings::new(Waveform::Square, 1000.0);
+        let generator = Generator::new(settings, RATE);
+        assert_eq!(generator.partials.len(), 11);
+        assert!(generator.partials.iter().all(|&(freq, _)| freq < RATE / 2.0));
+    }
+
+    #[test]
+    fn detune_and_inharmonicity_shift_the_partials() {
+        let waveform = Waveform::Harmonics {
+            count: 4,
+            inharmonicity: 0.01,
+        };
+        let mut settings = SignalSettings::new(waveform, 100.0);
+        settings.detune = 1200.0;
+        let generator = Generator::new(settings, RATE);
+        for (n, &(freq, _)) in (1..).zip(generator.partials.iter()) {
+            let harmonic = f64::from(n);
+            let expected = 200.0 * harmonic * (1.0 + 0.01 * harmonic * harmonic).sqrt();
+            assert!((freq - expected).abs() < 1e-9, "partial {}: {} Hz", n, freq);
+        }
+    }
+
+    #[test]
+    fn amplitude_sets_the_power() {
+        for &waveform in [Waveform::Sine, Waveform::Sawtooth, Waveform::Square].iter() {
+            let mut settings = SignalSettings::new(waveform, 100.0);
+            settings.amplitude = 0.5;
+            let samples = Generator::new(settings, RATE).take(44100).collect::<Vec<_>>();
+            let expected = 0.5 / 2f64.sqrt();
+            assert!((rms(&samples) - expected).abs() < 1e-3, "{:?}", waveform);
+        }
+    }
+
+    #[test]
+    fn envelope_and_noise() {
+        let mut settings = SignalSettings::new(Waveform::Sine, 100.0);
+        settings.amplitude = 0.0;
+        settings.noise = 0.1;
+        let noise = Generator::new(settings, RATE).take(44100).collect::<Vec<_>>();
+        assert!(noise.iter().all(|s| s.abs() <= 0.1));
+        // Uniform noise from -0.1 to 0.1.
+        assert!((rms(&noise) - 0.1 / 3f64.sqrt()).abs() < 1e-3);
+        let mut settings = SignalSettings::new(Waveform::Sine, 100.0);
+        settings.envelope = Envelope {
+            attack: 0.1,
+            decay: 0.2,
+        };
+        let generator = Generator::new(settings, RATE);
+        assert_eq!(generator.envelope(0.0), 0.0);
+        assert!((generator.envelope(0.05) - 0.5).abs() < 1e-12);
+        assert!((generator.envelope(0.1) - 1.0).abs() < 1e-12);
+        assert!((generator.envelope(0.3) - (-1.0f64).exp()).abs() < 1e-12);
+    }
+}
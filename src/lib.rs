@@ -0,0 +1,23 @@
+#![deny(bare_trait_objects)]
+#![warn(clippy::all)]
+
+//! Pitch detection for tuners, independent of where the audio comes from.
+
+#[cfg(feature = "alsa")]
+pub mod alsa_source;
+pub mod analyser;
+pub mod detector;
+pub mod filter;
+pub mod generator;
+pub mod mcleod;
+pub mod note;
+pub mod realfft;
+pub mod ring;
+pub mod sample;
+pub mod source;
+pub mod spectral;
+pub mod stabiliser;
+pub mod stft;
+pub mod threaded;
+pub mod wav;
+pub mod window;
@@ -1,54 +1,282 @@
 #![deny(bare_trait_objects)]
 #![warn(clippy::all)]
 
-mod alsa_source;
-mod analyser;
 mod cli;
 mod curses;
+mod devices;
 mod error;
 mod mic;
-mod note;
-mod sample;
+mod table;
 mod text;
 
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
 use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::time::{Duration, Instant};
 
-use analyser::Analyser;
-use cli::CLIData;
-use curses::{draw_state, init_curses, is_done};
-use mic::{open_microphone, MicSettings};
-use note::Position;
+use libc::pollfd;
+use rustfft::num_traits::Float;
+
+use offkey::alsa_source::AlsaSource;
+use offkey::analyser::Analyser;
+use offkey::filter::DcBlocker;
+use offkey::generator::Generator;
+use offkey::source::{AudioSource, IterSource, Pace};
+use offkey::stabiliser::Stabiliser;
+use offkey::threaded::ThreadedSource;
+use offkey::wav::WavSource;
+
+use cli::{AnalyzeData, CLIData, Subcommand};
+use curses::{draw_state, init_curses, read_command, Capture, Command, Heard};
+use devices::{capture_devices, write_device, Capabilities};
+use mic::{open_microphone, Negotiated};
+use table::Table;
 use text::Text;
 
 use snafu::ResultExt;
 
+/// How much the reference pitch changes per key press, in Hz.
+const REFERENCE_STEP: f64 = 1.0;
+/// Sample rate of synthetic signals, in Hz.
+const GENERATOR_RATE: f64 = 44100.0;
+/// How often sources that cannot be polled are read, in milliseconds.
+const TIMER_MILLIS: i32 = 20;
+/// Longest time spent reading audio before seeing to the keyboard and the
+/// screen.
+const DRAIN_LIMIT: Duration = Duration::from_millis(50);
+
 fn main() -> Result<(), error::Error> {
     // Do note that this one will kill the program in case of errors.
     let cli = CLIData::new();
-    let set = MicSettings {
-        access: Some(alsa::pcm::Access::RWInterleaved),
-        ..MicSettings::default()
-    };
-    let mic = open_microphone(&cli.device_name, set).context(error::AlsaDeviceSetup)?;
-    let mut analyser =
-        Analyser::<'_, f64>::new(&mic, 1750).context(error::AnalyserSetup)?;
-    let strings_file = File::open(&cli.text_data_file).context(error::TextFileRead)?;
+    match &cli.command {
+        Some(Subcommand::Analyze(args)) => return analyze(&cli, args),
+        Some(Subcommand::Devices) => return list_devices(),
+        None => (),
+    }
+    let pace = if cli.fast { Pace::Unthrottled } else { Pace::RealTime };
+    match (&cli.input, cli.generate) {
+        (Some(path), _) => {
+            let file = File::open(path).context(error::InputFileOpen)?;
+            let source = WavSource::new(BufReader::new(file), pace)
+                .context(error::InputFileSetup)?;
+            run(&cli, source, None)
+        }
+        (None, Some(signal)) => {
+            let generator = Generator::new(signal, GENERATOR_RATE);
+            let source = IterSource::new(generator, GENERATOR_RATE).with_pace(pace);
+            run(&cli, source, None)
+        }
+        (None, None) => {
+            let mic = open_microphone(&cli.device_name, cli.mic_settings())
+                .context(error::AlsaDeviceSetup)?;
+            let negotiated = Negotiated::current(&mic).context(error::AlsaDeviceSetup)?;
+            let capture = Capture {
+                device: cli.device_name.clone(),
+                negotiated,
+            };
+            let source = AlsaSource::new(mic).context(error::AnalyserSetup)?;
+            // Capture goes on in the background, so that the analysis and
+            // the screen cannot hold it up.
+            let source = ThreadedSource::spawn(source).context(error::CaptureThread)?;
+            run(&cli, source, Some(capture))
+        }
+    }
+}
+
+/// Run the tuner on audio from `source`, with the DC offset removed if asked
+/// to. `capture` describes the device the audio comes from, if it is one.
+fn run<S>(cli: &CLIData, source: S, capture: Option<Capture>) -> Result<(), error::Error>
+where
+    S: AudioSource<f64>,
+    S::Error: StdError + 'static,
+{
+    match cli.dc_block {
+        Some(cutoff) => tune(cli, DcBlocker::new(source, cutoff), capture),
+        None => tune(cli, source, capture),
+    }
+}
+
+/// Run the tuner on audio from `source` until the user quits.
+fn tune<S>(cli: &CLIData, source: S, capture: Option<Capture>) -> Result<(), error::Error>
+where
+    S: AudioSource<f64>,
+    S::Error: StdError + 'static,
+{
+    let detector = cli.detector();
+    let mut analyser = Analyser::new(
+        source,
+        cli.frames(),
+        || detector.build(),
+        cli.channel_settings(),
+        cli.thresholds(),
+    )
+    .context(error::ChannelSelection)?;
+    // `CLIData::new` makes sure the file is given when there is no subcommand.
+    let strings_path = cli.text_data_file.as_ref().expect("no text data file");
+    let strings_file = File::open(strings_path).context(error::TextFileRead)?;
     let text = Text::new(strings_file)?;
     let mut curses = init_curses().context(error::Curses)?;
-    while !is_done(&mut curses) {
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        if let Err(error) = analyser.read_data() {
-            analyser.recover(error).context(error::AlsaProcessing)?;
+    let mut reference = cli.reference;
+    let stabiliser = cli.stabiliser();
+    // Both are keyed by channel, which keeps the channels in order on screen.
+    let mut stabilisers = BTreeMap::new();
+    let mut latest = BTreeMap::new();
+    let processing = |e| error::Error::Processing { source: Box::new(e) };
+    loop {
+        let keyboard = pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
         };
-        analyser.do_fft();
-        if let Some(dominant) = analyser.dominant_frequency() {
-            dbg!(dominant);
-            let pos = Position::from_frequency(dominant);
-            if let Some(pos) = pos {
-                draw_state(&mut curses, &text, pos, dominant)
-                    .context(error::Curses)?;
+        let mut descriptors = vec![keyboard];
+        descriptors.extend(analyser.poll_descriptors().map_err(processing)?);
+        let timeout = if descriptors.len() > 1 { -1 } else { TIMER_MILLIS };
+        alsa::poll::poll(&mut descriptors, timeout).context(error::Poll)?;
+        let mut redraw = false;
+        if descriptors[0].revents != 0 {
+            // Curses may have taken in more than one key at a time.
+            while let Some(command) = read_command(&mut curses) {
+                match command {
+                    Command::Quit => return Ok(()),
+                    Command::RaiseReference => reference += REFERENCE_STEP,
+                    Command::LowerReference => {
+                        reference = (reference - REFERENCE_STEP).max(REFERENCE_STEP)
+                    }
+                }
+                redraw = true;
             }
         }
+        let audio = &descriptors[1..];
+        if audio.is_empty() {
+            // A single read gets all that is due since the last tick.
+            read_audio(&mut analyser, false).map_err(processing)?;
+        } else if analyser.poll_ready(audio).map_err(processing)? {
+            read_audio(&mut analyser, true).map_err(processing)?;
+        }
+        // Only the latest pitch is shown, so frames the analysis has fallen
+        // behind on are not worth the time.
+        analyser.skip_to_latest();
+        while let Some(analysis) = analyser.next_analysis() {
+            let channel = analysis.channel;
+            let reading = stabilisers
+                .entry(channel)
+                .or_insert_with(|| Stabiliser::new(stabiliser))
+                .update(analysis.estimate, reference);
+            let level = analysis.level;
+            latest.insert(channel, Heard { channel, reading, level });
+            redraw = true;
+        }
+        if redraw && !latest.is_empty() {
+            let heard = latest.values().cloned().collect::<Vec<_>>();
+            let xruns = analyser.xruns();
+            let capture = capture.as_ref();
+            draw_state(&mut curses, &text, &heard, reference, capture, xruns)
+                .context(error::Curses)?;
+        }
+    }
+}
+
+/// Read what the source has for now, recovering from what can be recovered
+/// from. With `drain`, it is read until it runs dry, but for no longer than
+/// `DRAIN_LIMIT` so that a source that never does cannot hold up the
+/// keyboard and the screen. Otherwise, it is read once.
+fn read_audio<T, S>(analyser: &mut Analyser<T, S>, drain: bool) -> Result<(), S::Error>
+where
+    T: Float,
+    S: AudioSource<T>,
+{
+    let started = Instant::now();
+    loop {
+        match analyser.read_data() {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(error) => analyser.recover(error)?,
+        }
+        if !drain || started.elapsed() >= DRAIN_LIMIT || analyser.is_finished() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Analyse a whole file as fast as possible and write the results as a table.
+fn analyze(cli: &CLIData, args: &AnalyzeData) -> Result<(), error::Error> {
+    let file = File::open(&args.file).context(error::InputFileOpen)?;
+    let source = WavSource::new(BufReader::new(file), Pace::Unthrottled)
+        .context(error::InputFileSetup)?;
+    match cli.dc_block {
+        Some(cutoff) => write_table(cli, args, DcBlocker::new(source, cutoff)),
+        None => write_table(cli, args, source),
+    }
+}
+
+fn write_table<S>(
+    cli: &CLIData,
+    args: &AnalyzeData,
+    source: S,
+) -> Result<(), error::Error>
+where
+    S: AudioSource<f64>,
+    S::Error: StdError + 'static,
+{
+    let detector = cli.detector();
+    let mut analyser = Analyser::new(
+        source,
+        cli.frames(),
+        || detector.build(),
+        cli.channel_settings(),
+        cli.thresholds(),
+    )
+    .context(error::ChannelSelection)?;
+    let out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).context(error::OutputWrite)?,
+        )),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    let mut table =
+        Table::new(out, args.format, cli.separate).context(error::OutputWrite)?;
+    let written = write_rows(&mut analyser, &mut table, cli.reference);
+    // The table is finished even if the analysis fails partway, so that the
+    // rows written before that still make a well-formed table.
+    let finished = table.finish().context(error::OutputWrite);
+    written?;
+    finished?;
+    Ok(())
+}
+
+/// Write a row for every frame of the source, until it runs out.
+fn write_rows<S, W>(
+    analyser: &mut Analyser<f64, S>,
+    table: &mut Table<W>,
+    reference: f64,
+) -> Result<(), error::Error>
+where
+    S: AudioSource<f64>,
+    S::Error: StdError + 'static,
+    W: Write,
+{
+    while !analyser.is_finished() {
+        analyser
+            .read_data()
+            .map_err(|e| error::Error::Processing { source: Box::new(e) })?;
+        while let Some(analysis) = analyser.next_analysis() {
+            table.write_row(&analysis, reference).context(error::OutputWrite)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the devices that can be captured from, with what they support.
+fn list_devices() -> Result<(), error::Error> {
+    let devices = capture_devices().context(error::DeviceListing)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for device in devices.iter() {
+        let capabilities = Capabilities::probe(&device.name);
+        write_device(&mut out, device, capabilities).context(error::OutputWrite)?;
     }
+    out.flush().context(error::OutputWrite)?;
     Ok(())
 }
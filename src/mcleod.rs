@@ -0,0 +1,172 @@
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Float;
+use rustfft::FFTnum;
+
+use crate::detector::{Estimate, PitchDetector};
+use crate::realfft::{fast_len, RealFft};
+
+/* ---------- constants ---------- */
+
+/// Key maxima of the NSDF this close to the highest one are good enough.
+const PEAK_THRESHOLD: f64 = 0.9;
+
+/* ---------- types ---------- */
+
+/// McLeod Pitch Method, a time-domain pitch detector.
+///
+/// This looks for the first lag at which the signal is nearly as similar to
+/// itself as it gets, using the normalised square difference function (NSDF).
+/// The value of the NSDF at the picked lag is the clarity of the estimate: 1
+/// for a perfectly periodic signal, closer to 0 for noise.
+pub struct McLeod<T> {
+    fft: RealFft<T>,
+    /// Number of samples in a frame.
+    frame_len: usize,
+    /// The frame followed by zeroes, and later the power spectrum.
+    padded: Vec<T>,
+    /// The spectrum of the frame, and later the autocorrelation.
+    spectrum: Vec<Complex<T>>,
+    nsdf: Vec<f64>,
+    /// Lags and heights of the key maxima of the NSDF.
+    maxima: Vec<(f64, f64)>,
+}
+
+/* ---------- main things ---------- */
+
+impl<T: FFTnum + Float + Default> McLeod<T> {
+    pub fn new() -> Self {
+        Self::with_size(0)
+    }
+
+    fn with_size(size: usize) -> Self {
+        // Padding to twice the size makes the circular correlation computed
+        // by the FFT equal to the linear one. Any more is harmless, so it is
+        // rounded up to a size the FFT is fast at.
+        let padded_size = fast_len(2 * size);
+        let fft = RealFft::new(padded_size);
+        McLeod {
+            frame_len: size,
+            padded: vec![T::zero(); padded_size],
+            spectrum: vec![Complex::default(); fft.spectrum_len()],
+            fft,
+            nsdf: vec![0.0; size / 2],
+            maxima: Vec::new(),
+        }
+    }
+}
+
+impl<T: FFTnum + Float + Default> Default for McLeod<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: FFTnum + Float + Default> PitchDetector<T> for McLeod<T> {
+    fn detect(&mut self, samples: &[T], sample_rate: f64) -> Option<Estimate> {
+        if samples.len() != self.frame_len {
+            *self = Self::with_size(samples.len());
+        }
+        self.compute_nsdf(samples);
+        self.find_key_maxima();
+        let (lag, clarity) = self.pick_peak()?;
+        Some(Estimate {
+            frequency: sample_rate / lag,
+            confidence: clarity.clamp(0.0, 1.0),
+        })
+    }
+}
+
+impl<T: FFTnum + Float> McLeod<T> {
+    fn compute_nsdf(&mut self, samples: &[T]) {
+        let size = samples.len().min(self.frame_len);
+        let samples = &samples[samples.len() - size..];
+        self.padded[..size].copy_from_slice(samples);
+        for targ in self.padded[size..].iter_mut() {
+            *targ = T::zero();
+        }
+        self.fft.process(&self.padded, &mut self.spectrum);
+        // The power spectrum of real input is real and symmetric, so it is
+        // rebuilt in full and transformed forward rather than inverted.
+        let len = self.padded.len();
+        for (k, bin) in self.spectrum.iter().enumerate() {
+            let power = bin.norm_sqr();
+            self.padded[k] = power;
+            if k > 0 && k < len - k {
+                self.padded[len - k] = power;
+            }
+        }
+        // The autocorrelation ends up in `spectrum`, scaled by the length.
+        self.fft.process(&self.padded, &mut self.spectrum);
+        let scale = len as f64;
+        let square = |i: usize| samples[i].to_f64().map_or(0.0, |s| s * s);
+        let mut energy = 2.0 * (0..size).map(square).sum::<f64>();
+        for (lag, nsdf) in self.nsdf.iter_mut().enumerate() {
+            if lag > 0 {
+                energy -= square(lag - 1) + square(size - lag);
+            }
+            let correlation = self.spectrum[lag].re.to_f64().unwrap_or(0.0) / scale;
+            *nsdf = if energy > 0.0 {
+                2.0 * correlation / energy
+            } else {
+                0.0
+            };
+        }
+    }
+
+    /// The lag and the height of the chosen NSDF peak, both interpolated.
+    fn pick_peak(&self) -> Option<(f64, f64)> {
+        let highest = self
+            .maxima
+            .iter()
+            .map(|&(_, value)| value)
+            .fold(f64::NEG_INFINITY, f64::max);
+        self.maxima
+            .iter()
+            .cloned()
+            .find(|&(_, value)| value >= PEAK_THRESHOLD * highest)
+            .filter(|&(lag, _)| lag > 0.0)
+    }
+
+    /// Interpolated highest points between each positive-going zero crossing
+    /// of the NSDF and the following negative-going one.
+    fn find_key_maxima(&mut self) {
+        let nsdf = &self.nsdf;
+        let res = &mut self.maxima;
+        res.clear();
+        // The peak at zero lag is of no interest.
+        let mut lag = nsdf.iter().position(|&v| v < 0.0).unwrap_or(nsdf.len());
+        while lag < nsdf.len() {
+            while lag < nsdf.len() && nsdf[lag] <= 0.0 {
+                lag += 1;
+            }
+            let mut best: Option<usize> = None;
+            while lag < nsdf.len() && nsdf[lag] > 0.0 {
+                if best.is_none_or(|b| nsdf[lag] > nsdf[b]) {
+                    best = Some(lag);
+                }
+                lag += 1;
+            }
+            // A peak cut off by the end of the NSDF is not a proper maximum.
+            if let (Some(best), true) = (best, lag < nsdf.len()) {
+                res.push(interpolate(nsdf, best));
+            }
+        }
+    }
+}
+
+/* ---------- helpers ---------- */
+
+/// The vertex of a parabola through a point and its neighbours.
+fn interpolate(values: &[f64], index: usize) -> (f64, f64) {
+    if index == 0 || index + 1 >= values.len() {
+        return (index as f64, values[index]);
+    }
+    let (left, centre, right) = (values[index - 1], values[index], values[index + 1]);
+    let curvature = left - 2.0 * centre + right;
+    if curvature >= 0.0 {
+        return (index as f64, centre);
+    }
+    let offset = 0.5 * (left - right) / curvature;
+    let height = centre - 0.25 * (left - right) * offset;
+    (index as f64 + offset, height)
+}
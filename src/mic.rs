@@ -1,14 +1,34 @@
-use alsa::pcm::{Access, Format, HwParams};
+use alsa::pcm::{Access, Format, Frames, HwParams};
 use alsa::{Direction, ValueOr, PCM};
 
+use offkey::alsa_source::FORMATS;
+
+/* ---------- types ---------- */
+
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct MicSettings {
     pub format: Option<Format>,
     pub channels: Option<u32>,
     pub rate: Option<u32>,
     pub access: Option<Access>,
+    /// Frames per period, the amount the device hands over at once.
+    pub period_size: Option<Frames>,
+    /// Frames in the whole ring buffer of the device.
+    pub buffer_size: Option<Frames>,
+}
+
+/// What the device actually agreed to, which may differ from what was asked
+/// for. The period and the buffer are left out, as they may grow later on,
+/// see `Xruns`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Negotiated {
+    pub format: Format,
+    pub channels: u32,
+    pub rate: u32,
 }
 
+/* ---------- main things ---------- */
+
 pub fn open_microphone(device: &str, settings: MicSettings) -> alsa::Result<PCM> {
     let mic = PCM::new(device, Direction::Capture, true)?;
     {
@@ -25,7 +45,49 @@ pub fn open_microphone(device: &str, settings: MicSettings) -> alsa::Result<PCM>
         if let Some(acc) = settings.access {
             params.set_access(acc)?;
         }
+        // The buffer holds a whole number of periods, so the period goes
+        // first.
+        if let Some(size) = settings.period_size {
+            params.set_period_size_near(size, ValueOr::Nearest)?;
+        }
+        if let Some(size) = settings.buffer_size {
+            params.set_buffer_size_near(size)?;
+        }
         mic.hw_params(&params)?;
     }
     Ok(mic)
 }
+
+impl Negotiated {
+    pub fn current(pcm: &PCM) -> alsa::Result<Self> {
+        let params = pcm.hw_params_current()?;
+        Ok(Negotiated {
+            format: params.get_format()?,
+            channels: params.get_channels()?,
+            rate: params.get_rate()?,
+        })
+    }
+}
+
+/* ---------- formats ---------- */
+
+/// ALSA name of a sample format the tuner can read.
+pub fn format_name(format: Format) -> Option<&'static str> {
+    FORMATS
+        .iter()
+        .find(|(known, _, _)| *known == format)
+        .map(|(_, name, _)| *name)
+}
+
+/// Parse an ALSA format name, like `S16_LE`, ignoring the case.
+pub fn parse_format(s: &str) -> Result<Format, String> {
+    FORMATS
+        .iter()
+        .find(|(_, name, _)| name.eq_ignore_ascii_case(s))
+        .map(|(format, _, _)| *format)
+        .ok_or_else(|| {
+            let names = FORMATS.iter().map(|(_, name, _)| *name);
+            let names = names.collect::<Vec<_>>().join(", ");
+            format!("unknown sample format '{}', expected one of: {}", s, names)
+        })
+}
@@ -1,4 +1,5 @@
 use std::cmp::Ordering;
+use std::fmt;
 use std::ops::Sub;
 
 use rustfft::num_traits::Float;
@@ -13,11 +14,23 @@ use Note::*;
 
 pub const SEMITONES_PER_OCTAVE: i32 = 12;
 pub const SEMITONES_PER_OCTAVE_F: f64 = 12.0;
+pub const CENTS_PER_SEMITONE: f64 = 100.0;
 pub const SUB_CONTRA: Octave = Octave(0);
-pub const SUB_CONTRA_A_FREQ: f64 = 27.5;
+/// The octave of the reference A.
+pub const FIRST: Octave = Octave(4);
 
 /* ---------- types ---------- */
 
+/// A frequency measured against the nearest note.
+#[derive(Debug, Clone, Copy)]
+pub struct Reading {
+    pub position: Position,
+    /// Deviation from the nearest note, positive if sharp, negative if flat.
+    pub cents: f64,
+    /// The exact frequency of the nearest note.
+    pub target_freq: f64,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Position {
     pub octave: Octave,
@@ -30,8 +43,8 @@ pub struct Position {
 /// This is a very thin wrapper over an octave number according to the
 /// scientific naming system. Sub Contra octave is 0, and the First octave is
 /// 4.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Deserialize)]
 #[serde(transparent)]
-#[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Hash, Deserialize)]
 pub struct Octave(pub i32);
 
 #[repr(i32)]
@@ -46,22 +59,64 @@ pub enum Note {
     B = 11,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy, Hash, Deserialize)]
+#[derive(Debug, PartialEq, Clone, Copy, Hash, Default, Deserialize)]
 pub enum Accidental {
     Flat,
     Sharp,
+    #[default]
     Natural,
 }
 
+/* ---------- readings ---------- */
+
+impl Reading {
+    /// Reading from a frequency.
+    ///
+    /// `reference` is the frequency of A in the First octave.
+    pub fn from_frequency<T: Float>(freq: T, reference: f64) -> Option<Self> {
+        let exact = semitones_from_frequency(freq, reference)?;
+        // This may well land in the next octave, which is handled by
+        // `Position::from_semitone_offset`.
+        Reading::relative_to(exact, exact.round() as i32, reference)
+    }
+
+    /// Reading of a pitch against a given note, which need not be the
+    /// nearest one.
+    ///
+    /// Both the pitch and the note are semitone offsets wrt C in the Sub
+    /// Contra octave.
+    pub fn relative_to(exact: f64, semitones: i32, reference: f64) -> Option<Self> {
+        let position = Position::from_semitone_offset(semitones)?;
+        Some(Reading {
+            position,
+            cents: (exact - f64::from(semitones)) * CENTS_PER_SEMITONE,
+            target_freq: position.frequency(reference),
+        })
+    }
+}
+
+/// Offset of a frequency wrt C in the Sub Contra octave, in fractional
+/// semitones.
+pub fn semitones_from_frequency<T: Float>(freq: T, reference: f64) -> Option<f64> {
+    let freq = freq.to_f64()
+        .and_then(|f| if f > 0.0 { Some(f) } else { None })?;
+    let octave = Octave::from_frequency(freq, reference);
+    let offset = octave.frequency_offset(freq, reference);
+    Some(f64::from(octave.0 * SEMITONES_PER_OCTAVE) + offset)
+}
+
 /* ---------- joint manipulation ---------- */
 
 impl Position {
-    /// Position from a frequency.
-    pub fn from_frequency<T: Float>(freq: T) -> Option<Self> {
-        let freq = freq.to_f64()
-            .and_then(|f| if f > 0.0 { Some(f) } else { None })?;
-        let octave = Octave::from_frequency(freq);
-        let offset = octave.frequency_offset(freq);
+    /// Position from a frequency, rounded to the nearest note.
+    pub fn from_frequency<T: Float>(freq: T, reference: f64) -> Option<Self> {
+        Reading::from_frequency(freq, reference).map(|reading| reading.position)
+    }
+
+    /// Position from a semitone offset wrt C in the Sub Contra octave.
+    pub fn from_semitone_offset(semitones: i32) -> Option<Self> {
+        let octave = Octave(semitones.div_euclid(SEMITONES_PER_OCTAVE));
+        let offset = semitones.rem_euclid(SEMITONES_PER_OCTAVE);
         if let Some(note) = Note::from_semitone(offset) {
             Some(Position::from_parts(octave, note, Natural))
         } else {
@@ -94,6 +149,10 @@ impl Position {
         from_octave + from_note + from_accidental
     }
 
+    /// The exact frequency of this position.
+    pub fn frequency(&self, reference: f64) -> f64 {
+        self.octave.note_frequency(self.note, self.accidental, reference)
+    }
 }
 
 impl PartialEq for Position {
@@ -108,38 +167,39 @@ impl PartialOrd for Position {
     }
 }
 
+/// Scientific pitch notation, like `C#4` or `Bb3`.
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}", self.note, self.accidental, self.octave.0)
+    }
+}
+
 /* ---------- octave manipulation ---------- */
 
 impl Octave {
-    pub fn from_frequency(freq: f64) -> Self {
-        let octave = (freq / SUB_CONTRA.lower_frequency()).log2();
+    pub fn from_frequency(freq: f64, reference: f64) -> Self {
+        let octave = (freq / SUB_CONTRA.lower_frequency(reference)).log2();
         Octave(octave.floor() as i32)
     }
 
-    pub fn lower_frequency(self) -> f64 {
-        self.note_frequency(C, Natural)
+    pub fn lower_frequency(self, reference: f64) -> f64 {
+        self.note_frequency(C, Natural, reference)
     }
 
-    pub fn frequency_offset(self, freq: f64) -> i32 {
-        let semitone = (freq / self.lower_frequency()).log2() * SEMITONES_PER_OCTAVE_F;
-        semitone.round() as i32
+    /// Offset of a frequency from C in this octave, in fractional semitones.
+    pub fn frequency_offset(self, freq: f64, reference: f64) -> f64 {
+        (freq / self.lower_frequency(reference)).log2() * SEMITONES_PER_OCTAVE_F
     }
 
-    pub fn note_frequency(self, note: Note, acc: Accidental) -> f64 {
-        let semitones_from_octave = self.0 * SEMITONES_PER_OCTAVE;
+    /// Frequency of a note in this octave, given the frequency of A in the
+    /// First octave.
+    pub fn note_frequency(self, note: Note, acc: Accidental, reference: f64) -> f64 {
+        let semitones_from_octave = (self.0 - FIRST.0) * SEMITONES_PER_OCTAVE;
         let semitones_from_notes = note - A;
         let total = f64::from(
             semitones_from_octave + semitones_from_notes + acc.semitone_shift(),
         );
-        SUB_CONTRA_A_FREQ * (total / SEMITONES_PER_OCTAVE_F).exp2()
-    }
-}
-
-impl Eq for Octave { }
-
-impl Ord for Octave {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.0.cmp(&other.0)
+        reference * (total / SEMITONES_PER_OCTAVE_F).exp2()
     }
 }
 
@@ -192,6 +252,12 @@ impl Sub for Note {
 
 impl Eq for Note { }
 
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
 /* ---------- accidental manipulation ---------- */
 
 impl Accidental {
@@ -205,10 +271,122 @@ impl Accidental {
     }
 }
 
-impl Default for Accidental {
-    fn default() -> Self {
-        Natural
+impl Eq for Accidental { }
+
+impl fmt::Display for Accidental {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Flat => "b",
+            Sharp => "#",
+            Natural => "",
+        };
+        f.write_str(symbol)
     }
 }
 
-impl Eq for Accidental { }
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frequency this many cents off another one.
+    fn detuned(freq: f64, cents: f64) -> f64 {
+        freq * (cents / 1200.0).exp2()
+    }
+
+    /// How far one frequency is from another, in cents.
+    fn cents_apart(freq: f64, reference: f64) -> f64 {
+        1200.0 * (freq / reference).log2()
+    }
+
+    fn reading(freq: f64, reference: f64) -> (String, f64, f64) {
+        let reading =
+            Reading::from_frequency(freq, reference).expect("a positive frequency");
+        (reading.position.to_string(), reading.cents, reading.target_freq)
+    }
+
+    #[test]
+    fn semitones_from_frequencies() {
+        let close = |freq: f64, reference: f64, expected: f64| {
+            let semitones = semitones_from_frequency(freq, reference).expect("positive");
+            assert!((semitones - expected).abs() < 1e-9, "{} Hz: {}", freq, semitones);
+        };
+        close(440.0, 440.0, 57.0);
+        close(27.5, 440.0, 9.0);
+        close(detuned(440.0, -5700.0), 440.0, 0.0);
+        close(detuned(440.0, 50.0), 440.0, 57.5);
+        close(432.0, 432.0, 57.0);
+        close(440.0, 415.0, 57.0 + cents_apart(440.0, 415.0) / 100.0);
+        assert_eq!(semitones_from_frequency(0.0, 440.0), None);
+        assert_eq!(semitones_from_frequency(-440.0, 440.0), None);
+        assert_eq!(semitones_from_frequency(f64::NAN, 440.0), None);
+    }
+
+    #[test]
+    fn positions_from_semitone_offsets() {
+        let cases = [
+            (0, "C0"),
+            (1, "C#0"),
+            (3, "D#0"),
+            (10, "A#0"),
+            (11, "B0"),
+            (12, "C1"),
+            (-1, "B-1"),
+            (57, "A4"),
+            (60, "C5"),
+        ];
+        for &(semitones, name) in cases.iter() {
+            let position =
+                Position::from_semitone_offset(semitones).expect("a valid offset");
+            assert_eq!(position.to_string(), name);
+            assert_eq!(position.semitone_offset(), semitones);
+        }
+    }
+
+    #[test]
+    fn readings() {
+        let (name, cents, target) = reading(detuned(440.0, 23.0), 440.0);
+        assert_eq!(name, "A4");
+        assert!((cents - 23.0).abs() < 1e-9, "{} cents", cents);
+        assert!((target - 440.0).abs() < 1e-9, "{} Hz", target);
+        let (name, cents, _) = reading(detuned(440.0, 70.0), 440.0);
+        assert_eq!(name, "A#4");
+        assert!((cents - -30.0).abs() < 1e-9, "{} cents", cents);
+        assert!(Reading::from_frequency(0.0, 440.0).is_none());
+    }
+
+    #[test]
+    fn readings_across_the_octave_boundary() {
+        // B4 and C5, either side of the point where the octave number changes.
+        let c5 = detuned(440.0, 300.0);
+        let (name, cents, target) = reading(detuned(c5, -10.0), 440.0);
+        assert_eq!(name, "C5");
+        assert!((cents - -10.0).abs() < 1e-9, "{} cents", cents);
+        assert!((target - c5).abs() < 1e-9, "{} Hz", target);
+        let (name, cents, _) = reading(detuned(c5, -60.0), 440.0);
+        assert_eq!(name, "B4");
+        assert!((cents - 40.0).abs() < 1e-9, "{} cents", cents);
+        let (name, cents, _) = reading(detuned(c5, 10.0), 440.0);
+        assert_eq!(name, "C5");
+        assert!((cents - 10.0).abs() < 1e-9, "{} cents", cents);
+    }
+
+    #[test]
+    fn readings_against_other_references() {
+        let (name, cents, target) = reading(432.0, 432.0);
+        assert_eq!(name, "A4");
+        assert!(cents.abs() < 1e-9, "{} cents", cents);
+        assert!((target - 432.0).abs() < 1e-9, "{} Hz", target);
+        // Concert A against the A of 432 Hz.
+        let (name, cents, _) = reading(440.0, 432.0);
+        assert_eq!(name, "A4");
+        assert!((cents - cents_apart(440.0, 432.0)).abs() < 1e-9, "{} cents", cents);
+        // And against baroque pitch, a semitone down.
+        let (name, cents, target) = reading(440.0, 415.0);
+        assert_eq!(name, "A#4");
+        let expected = cents_apart(440.0, 415.0) - 100.0;
+        assert!((cents - expected).abs() < 1e-9, "{} cents", cents);
+        assert!((target - detuned(415.0, 100.0)).abs() < 1e-9, "{} Hz", target);
+    }
+}
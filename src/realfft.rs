@@ -0,0 +1,212 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Float;
+use rustfft::{FFTnum, FFTplanner, FFT};
+
+/* ---------- types ---------- */
+
+/// Fourier transform of real input, which only computes the bins from zero
+/// to the Nyquist frequency, as the rest mirror them.
+///
+/// For even sizes, this is a complex transform of half the size, with the
+/// even samples as the real parts and the odd ones as the imaginary parts,
+/// followed by a pass that pulls the two halves apart. Odd sizes fall back to
+/// a complex transform of the whole size.
+pub struct RealFft<T> {
+    len: usize,
+    fft: Arc<dyn FFT<T>>,
+    /// exp(-2 pi i k / len), for k from 0 to len / 2, for even sizes only.
+    twiddles: Vec<Complex<T>>,
+    packed: Vec<Complex<T>>,
+    transformed: Vec<Complex<T>>,
+}
+
+/* ---------- main things ---------- */
+
+impl<T: FFTnum + Float> RealFft<T> {
+    pub fn new(len: usize) -> Self {
+        let even = len.is_multiple_of(2);
+        let complex_len = if even { len / 2 } else { len };
+        let twiddles = if even {
+            (0..=len / 2).map(|k| twiddle(k, len)).collect()
+        } else {
+            Vec::new()
+        };
+        RealFft {
+            len,
+            fft: FFTplanner::new(false).plan_fft(complex_len),
+            twiddles,
+            packed: vec![Complex::new(T::zero(), T::zero()); complex_len],
+            transformed: vec![Complex::new(T::zero(), T::zero()); complex_len],
+        }
+    }
+
+    /// Number of samples transformed at once.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of bins in the output, from zero to the Nyquist frequency.
+    pub fn spectrum_len(&self) -> usize {
+        if self.len == 0 {
+            0
+        } else {
+            self.len / 2 + 1
+        }
+    }
+
+    /// Transform `input`, which must be `len` long, into `output`, which must
+    /// be `spectrum_len` long. This does not allocate.
+    pub fn process(&mut self, input: &[T], output: &mut [Complex<T>]) {
+        assert_eq!(input.len(), self.len, "wrong size of the FFT input");
+        assert_eq!(output.len(), self.spectrum_len(), "wrong size of the FFT output");
+        if !self.len.is_multiple_of(2) {
+            for (targ, &src) in self.packed.iter_mut().zip(input.iter()) {
+                *targ = Complex::new(src, T::zero());
+            }
+            self.fft.process(&mut self.packed, &mut self.transformed);
+            output.copy_from_slice(&self.transformed[..output.len()]);
+            return;
+        }
+        if self.len == 0 {
+            return;
+        }
+        for (targ, pair) in self.packed.iter_mut().zip(input.chunks_exact(2)) {
+            *targ = Complex::new(pair[0], pair[1]);
+        }
+        self.fft.process(&mut self.packed, &mut self.transformed);
+        // With Z the transform of the packed samples, the transforms of the
+        // even and odd samples are E[k] = (Z[k] + Z*[M - k]) / 2 and
+        // O[k] = -i (Z[k] - Z*[M - k]) / 2, where M is the half size, and the
+        // whole thing is E[k] + exp(-2 pi i k / N) O[k].
+        let half = self.transformed.len();
+        let one_half = T::from(0.5).unwrap_or_else(T::zero);
+        let minus_half_i = Complex::new(T::zero(), -one_half);
+        let bins = output.iter_mut().zip(self.twiddles.iter());
+        for (k, (bin, &twiddle)) in bins.enumerate() {
+            let z = self.transformed[k % half];
+            let mirror = self.transformed[(half - k % half) % half].conj();
+            let even = (z + mirror).scale(one_half);
+            let odd = (z - mirror) * minus_half_i;
+            *bin = even + twiddle * odd;
+        }
+    }
+}
+
+/// The shortest length from `len` up that the transform is fast at: an even
+/// one, with half of it made of no prime factors but 2, 3 and 5.
+pub fn fast_len(len: usize) -> usize {
+    if len == 0 {
+        return 0;
+    }
+    let mut half = len.div_ceil(2).max(1);
+    while !is_smooth(half) {
+        half += 1;
+    }
+    2 * half
+}
+
+/* ---------- helpers ---------- */
+
+fn is_smooth(mut n: usize) -> bool {
+    for factor in [2, 3, 5].iter() {
+        while n.is_multiple_of(*factor) {
+            n /= factor;
+        }
+    }
+    n == 1
+}
+
+fn twiddle<T: Float>(k: usize, len: usize) -> Complex<T> {
+    let angle = -2.0 * PI * k as f64 / len as f64;
+    let part = |value: f64| T::from(value).unwrap_or_else(T::zero);
+    Complex::new(part(angle.cos()), part(angle.sin()))
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bins of the complex transform of `input`, up to the Nyquist
+    /// frequency.
+    fn complex_spectrum<T: FFTnum + Float>(input: &[T]) -> Vec<Complex<T>> {
+        let mut complex = input.iter()
+            .map(|&sample| Complex::new(sample, T::zero()))
+            .collect::<Vec<_>>();
+        let mut output = vec![Complex::new(T::zero(), T::zero()); input.len()];
+        FFTplanner::new(false).plan_fft(input.len()).process(&mut complex, &mut output);
+        output.truncate(input.len() / 2 + 1);
+        output
+    }
+
+    /// Largest difference from the complex transform, relative to the
+    /// loudest bin.
+    fn error_against_complex<T: FFTnum + Float>(len: usize) -> f64 {
+        // Something with no symmetry to hide mistakes behind.
+        let input = (0..len)
+            .map(|i| {
+                let t = i as f64;
+                (0.37 * t).sin() + 0.5 * (1.91 * t + 0.2).cos() + 0.01 * t
+            })
+            .map(|sample| T::from(sample).unwrap_or_else(T::zero))
+            .collect::<Vec<_>>();
+        let mut fft = RealFft::new(len);
+        let mut output = vec![Complex::new(T::zero(), T::zero()); fft.spectrum_len()];
+        fft.process(&input, &mut output);
+        let expected = complex_spectrum(&input);
+        assert_eq!(output.len(), expected.len(), "{} samples", len);
+        let norm = |bin: Complex<T>| bin.norm().to_f64().unwrap_or(f64::NAN);
+        let loudest = expected.iter().cloned().map(norm).fold(0.0, f64::max);
+        output.into_iter()
+            .zip(expected)
+            .map(|(bin, expected)| norm(bin - expected) / loudest)
+            .fold(0.0, f64::max)
+    }
+
+    /// Even and odd sizes, both small and those of the frames in use.
+    const LENGTHS: [usize; 9] = [1, 2, 3, 8, 15, 4410, 4500, 77175, 77760];
+
+    #[test]
+    fn matches_complex_fft_f64() {
+        for &len in LENGTHS.iter() {
+            let error = error_against_complex::<f64>(len);
+            assert!(error < 1e-12, "{} samples: off by {}", len, error);
+        }
+    }
+
+    #[test]
+    fn matches_complex_fft_f32() {
+        for &len in LENGTHS.iter() {
+            let error = error_against_complex::<f32>(len);
+            assert!(error < 1e-4, "{} samples: off by {}", len, error);
+        }
+    }
+
+    #[test]
+    fn empty_transform() {
+        let mut fft = RealFft::<f64>::new(0);
+        assert!(fft.is_empty());
+        assert_eq!(fft.spectrum_len(), 0);
+        fft.process(&[], &mut []);
+    }
+
+    #[test]
+    fn fast_lengths() {
+        assert_eq!(fast_len(0), 0);
+        assert_eq!(fast_len(1), 2);
+        assert_eq!(fast_len(4410), 4500);
+        assert_eq!(fast_len(77175), 77760);
+        for len in 1..1000 {
+            let fast = fast_len(len);
+            assert!(fast >= len && fast.is_multiple_of(2) && is_smooth(fast / 2));
+        }
+    }
+}
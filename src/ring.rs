@@ -0,0 +1,94 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/* ---------- types ---------- */
+
+/// The writing end of a ring buffer with a single writer and a single
+/// reader, which never block or lock.
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The reading end of a ring buffer, see `Producer`.
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    slots: Box<[UnsafeCell<T>]>,
+    /// Always a power of two, so that the counters below can wrap around.
+    mask: usize,
+    /// Items written so far, only ever changed by the producer.
+    written: AtomicUsize,
+    /// Items read so far, only ever changed by the consumer.
+    read: AtomicUsize,
+}
+
+// A slot is only written by the producer while it is free and only read by
+// the consumer while it is taken, and the counters say which is which.
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+/* ---------- main things ---------- */
+
+/// Make a ring buffer holding at least `capacity` items.
+pub fn ring<T: Copy + Default>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let slots = (0..capacity).map(|_| UnsafeCell::new(T::default())).collect();
+    let shared = Arc::new(Shared {
+        slots,
+        mask: capacity - 1,
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+    });
+    let producer = Producer {
+        shared: Arc::clone(&shared),
+    };
+    (producer, Consumer { shared })
+}
+
+impl<T: Copy> Producer<T> {
+    /// Number of items that can be pushed right now.
+    pub fn free(&self) -> usize {
+        let written = self.shared.written.load(Ordering::Relaxed);
+        let read = self.shared.read.load(Ordering::Acquire);
+        self.shared.slots.len() - written.wrapping_sub(read)
+    }
+
+    /// Push as many of `items` as there is room for, returning how many.
+    pub fn push(&mut self, items: &[T]) -> usize {
+        let written = self.shared.written.load(Ordering::Relaxed);
+        let count = items.len().min(self.free());
+        for (i, &item) in items[..count].iter().enumerate() {
+            let slot = &self.shared.slots[written.wrapping_add(i) & self.shared.mask];
+            // The slot is free, so the consumer does not look at it.
+            unsafe { *slot.get() = item };
+        }
+        let written = written.wrapping_add(count);
+        self.shared.written.store(written, Ordering::Release);
+        count
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Move everything there is to `buf`, returning the number of items.
+    pub fn pop_into(&mut self, buf: &mut Vec<T>) -> usize {
+        let read = self.shared.read.load(Ordering::Relaxed);
+        let written = self.shared.written.load(Ordering::Acquire);
+        let count = written.wrapping_sub(read);
+        buf.extend((0..count).map(|i| {
+            let slot = &self.shared.slots[read.wrapping_add(i) & self.shared.mask];
+            // The slot is taken, so the producer does not touch it.
+            unsafe { *slot.get() }
+        }));
+        let read = read.wrapping_add(count);
+        self.shared.read.store(read, Ordering::Release);
+        count
+    }
+
+    /// True if there is nothing to pop.
+    pub fn is_empty(&self) -> bool {
+        let read = self.shared.read.load(Ordering::Relaxed);
+        self.shared.written.load(Ordering::Acquire) == read
+    }
+}
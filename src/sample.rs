@@ -1,24 +1,6 @@
-/* ---------- A silly thing to abstract away 0.5 ---------- */
-
-pub trait Normal {
-    fn midpoint() -> Self;
-}
-
-impl Normal for f32 {
-    fn midpoint() -> Self {
-        0.5
-    }
-}
-
-impl Normal for f64 {
-    fn midpoint() -> Self {
-        0.5
-    }
-}
-
 /* ---------- The main thing ---------- */
 
-pub trait FromSample<T>: Normal {
+pub trait FromSample<T> {
     fn from_sample(sample: T) -> Self;
 }
 
@@ -45,30 +27,30 @@ impl<T> FromAnySample for T where
         + FromSample<f64>
 {}
 
-macro_rules! impl_from_int_lossless {
+// Both kinds of integers end up in [-1, 1), centred on zero. Signed
+// integers are divided by the magnitude of their minimum, and unsigned ones
+// are first shifted down by their midpoint.
+
+macro_rules! impl_from_signed {
     ($float:ident; $($integer:ident),+) => {
         $(
             impl FromSample<$integer> for $float {
                 fn from_sample(sample: $integer) -> $float {
-                    let min = $float::from($integer::min_value());
-                    let max = $float::from($integer::max_value());
-                    let s = $float::from(sample);
-                    (s - min) / (max - min)
+                    let full_scale = -($integer::MIN as $float);
+                    sample as $float / full_scale
                 }
             }
         )+
     }
 }
 
-macro_rules! impl_from_int_lossy {
+macro_rules! impl_from_unsigned {
     ($float:ident; $($integer:ident),+) => {
         $(
             impl FromSample<$integer> for $float {
                 fn from_sample(sample: $integer) -> $float {
-                    let min = $integer::min_value() as $float;
-                    let max = $integer::max_value() as $float;
-                    let s = sample as $float;
-                    (s - min) / (max - min)
+                    let midpoint = ($integer::MAX / 2 + 1) as $float;
+                    (sample as $float - midpoint) / midpoint
                 }
             }
         )+
@@ -87,9 +69,10 @@ macro_rules! impl_for_float_identity {
     }
 }
 
-impl_from_int_lossless!(f32; i8, u8, i16, u16);
-impl_from_int_lossless!(f64; i8, u8, i16, u16, i32, u32);
-impl_from_int_lossy!(f32; i32, u32);
+impl_from_signed!(f32; i8, i16, i32);
+impl_from_signed!(f64; i8, i16, i32);
+impl_from_unsigned!(f32; u8, u16, u32);
+impl_from_unsigned!(f64; u8, u16, u32);
 impl_for_float_identity!(f32, f64);
 
 impl FromSample<f32> for f64 {
@@ -103,3 +86,176 @@ impl FromSample<f64> for f32 {
         sample as f32
     }
 }
+
+/* ---------- raw samples ---------- */
+
+/// How samples are laid out in a stream of bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawFormat {
+    pub encoding: Encoding,
+    /// Bytes per sample, from 1 to 4 for integers, 4 or 8 for floats.
+    pub width: usize,
+    /// Bits that carry the value, counting from the least significant one.
+    /// Any bits above them are padding.
+    pub bits: usize,
+    pub byte_order: ByteOrder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl RawFormat {
+    /// A format where all bits of a sample carry the value.
+    pub const fn new(encoding: Encoding, width: usize, byte_order: ByteOrder) -> Self {
+        RawFormat {
+            encoding,
+            width,
+            bits: 8 * width,
+            byte_order,
+        }
+    }
+
+    /// A format with only the lowest `bits` bits of each sample used.
+    pub const fn padded(self, bits: usize) -> Self {
+        RawFormat { bits, ..self }
+    }
+
+    /// Decode whole samples from `bytes` and append them to `buf`.
+    pub fn decode<T: FromAnySample>(&self, bytes: &[u8], buf: &mut Vec<T>) {
+        // Integers are moved to the top of 32 bits, which drops the padding
+        // and brings every width to the same full scale.
+        let shift = 64 - self.bits.clamp(1, 32) as u32;
+        let byte_order = self.byte_order;
+        buf.extend(bytes.chunks_exact(self.width).map(|chunk| {
+            let raw = match byte_order {
+                ByteOrder::Little => chunk.iter().rev().fold(0, accumulate),
+                ByteOrder::Big => chunk.iter().fold(0, accumulate),
+            };
+            match (self.encoding, self.width) {
+                (Encoding::Signed, _) => {
+                    T::from_sample(((raw << shift) as i64 >> 32) as i32)
+                }
+                (Encoding::Unsigned, _) => T::from_sample(((raw << shift) >> 32) as u32),
+                (Encoding::Float, 8) => T::from_sample(f64::from_bits(raw)),
+                (Encoding::Float, _) => T::from_sample(f32::from_bits(raw as u32)),
+            }
+        }));
+    }
+}
+
+/* ---------- helpers ---------- */
+
+fn accumulate(acc: u64, byte: &u8) -> u64 {
+    acc << 8 | u64::from(*byte)
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ByteOrder::*;
+    use Encoding::*;
+
+    #[test]
+    fn integer_extremes() {
+        assert_eq!(f64::from_sample(i16::MIN), -1.0);
+        assert_eq!(f64::from_sample(0i16), 0.0);
+        assert_eq!(f64::from_sample(i16::MAX), 1.0 - 0.5f64.powi(15));
+        assert_eq!(f64::from_sample(u8::MIN), -1.0);
+        assert_eq!(f64::from_sample(128u8), 0.0);
+        assert_eq!(f64::from_sample(u8::MAX), 1.0 - 0.5f64.powi(7));
+        assert_eq!(f64::from_sample(i32::MIN), -1.0);
+        assert_eq!(f64::from_sample(0i32), 0.0);
+        assert_eq!(f64::from_sample(i32::MAX), 1.0 - 0.5f64.powi(31));
+        // The top of 32 bits rounds to 1 in single precision, so only the
+        // bottom is checked there.
+        assert_eq!(f32::from_sample(i16::MIN), -1.0);
+        assert!(f32::from_sample(i16::MAX) < 1.0);
+        assert_eq!(f32::from_sample(u8::MIN), -1.0);
+        assert!(f32::from_sample(u8::MAX) < 1.0);
+        assert_eq!(f32::from_sample(i32::MIN), -1.0);
+    }
+
+    #[test]
+    fn decoding_raw_samples() {
+        let s24_3le = RawFormat::new(Signed, 3, Little);
+        let s24_le = RawFormat::new(Signed, 4, Little).padded(24);
+        let s20_le = RawFormat::new(Signed, 4, Little).padded(20);
+        let u16_le = RawFormat::new(Unsigned, 2, Little);
+        let float_le = RawFormat::new(Float, 4, Little);
+        let float64_be = RawFormat::new(Float, 8, Big);
+        let cases: &[(&str, RawFormat, &[u8], &[f64])] = &[
+            (
+                "24-bit packed",
+                s24_3le,
+                &[0x00, 0x00, 0x80, 0xff, 0xff, 0x7f, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00],
+                &[-1.0, 1.0 - 0.5f64.powi(23), 0.5, 0.5f64.powi(23)],
+            ),
+            (
+                "24 bits in 32, with the padding ignored",
+                s24_le,
+                &[0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0xc0, 0xff, 0x00, 0x00, 0xc0, 0x12],
+                &[0.5, -0.5, -0.5],
+            ),
+            (
+                "20 bits in 32",
+                s20_le,
+                &[0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x04, 0x00, 0xff, 0xff, 0xff, 0xff],
+                &[-1.0, 0.5, -0.5f64.powi(19)],
+            ),
+            (
+                "16-bit big-endian",
+                RawFormat::new(Signed, 2, Big),
+                &[0x40, 0x00, 0x80, 0x00, 0xff, 0xff],
+                &[0.5, -1.0, -0.5f64.powi(15)],
+            ),
+            (
+                "24-bit packed big-endian",
+                RawFormat::new(Signed, 3, Big),
+                &[0x40, 0x00, 0x00, 0xc0, 0x00, 0x00],
+                &[0.5, -0.5],
+            ),
+            (
+                "8-bit unsigned",
+                RawFormat::new(Unsigned, 1, Little),
+                &[0x80, 0x00, 0xc0],
+                &[0.0, -1.0, 0.5],
+            ),
+            (
+                "16-bit unsigned, with a trailing partial sample",
+                u16_le,
+                &[0x00, 0xc0, 0x00, 0x00, 0x12],
+                &[0.5, -1.0],
+            ),
+            (
+                "32-bit float",
+                float_le,
+                &[0x00, 0x00, 0x80, 0x3e, 0x00, 0x00, 0x40, 0xbf],
+                &[0.25, -0.75],
+            ),
+            (
+                "64-bit float big-endian",
+                float64_be,
+                &[0xbf, 0xe8, 0, 0, 0, 0, 0, 0, 0x3f, 0xc0, 0, 0, 0, 0, 0, 0],
+                &[-0.75, 0.125],
+            ),
+        ];
+        for &(name, format, bytes, expected) in cases.iter() {
+            let mut buf = Vec::<f64>::new();
+            format.decode(bytes, &mut buf);
+            assert_eq!(buf, expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::convert::Infallible;
+use std::time::Instant;
+
+use libc::pollfd;
+
+/* ---------- constants ---------- */
+
+/// Number of samples an `IterSource` hands out per read, unless told
+/// otherwise.
+const DEFAULT_CHUNK_LEN: usize = 1024;
+
+/* ---------- types ---------- */
+
+/// Something audio can be read from, a chunk at a time.
+pub trait AudioSource<T> {
+    type Error;
+
+    /// Sample rate of the audio, in Hz.
+    fn sample_rate(&self) -> f64;
+
+    /// Number of channels in the audio.
+    fn channels(&self) -> usize {
+        1
+    }
+
+    /// Read whatever is available now, as normalised samples with the
+    /// channels interleaved. An empty chunk only means that there is nothing
+    /// new yet.
+    fn read(&mut self) -> Result<&[T], Self::Error>;
+
+    /// Try to carry on after a failed read, giving the error back if that is
+    /// not possible.
+    fn recover(&mut self, error: Self::Error) -> Result<(), Self::Error> {
+        Err(error)
+    }
+
+    /// True if there will be no more audio. Live sources never run out.
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    /// Descriptors to `poll` to find out when there is audio to read. Sources
+    /// without any are meant to be read on a timer.
+    fn poll_descriptors(&self) -> Result<Vec<pollfd>, Self::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Whether the descriptors, after a `poll`, show that there is something
+    /// to read, or an error to learn about by reading.
+    fn poll_ready(&self, _descriptors: &[pollfd]) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    /// What audio was lost so far, for sources that can lose any.
+    fn xruns(&self) -> Option<Xruns> {
+        None
+    }
+}
+
+/// Audio a live source could not keep up with.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Xruns {
+    /// Number of times audio was lost because it was not read in time.
+    pub overruns: usize,
+    /// Number of times the device was suspended and had to be resumed.
+    pub suspends: usize,
+    /// Size of the chunks the source currently reads, in frames. It grows
+    /// with every overrun, up to a limit.
+    pub period_size: usize,
+    /// Size of the buffer of the device, in frames. It grows along with the
+    /// period.
+    pub buffer_size: usize,
+    /// Frames read from the source but thrown away, as whatever was to take
+    /// them was not keeping up.
+    pub dropped: usize,
+}
+
+/// Audio from an iterator over mono samples, for example a vector held in
+/// memory.
+pub struct IterSource<I, T> {
+    samples: I,
+    chunk_len: usize,
+    sample_rate: f64,
+    clock: Clock,
+    finished: bool,
+    /// Samples from the latest read.
+    data: Vec<T>,
+}
+
+/// How quickly audio that is already there is read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    /// No faster than it would be played back.
+    RealTime,
+    /// As fast as the data can be processed.
+    Unthrottled,
+}
+
+/// Keeps track of how many frames of a source are due at its pace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Clock {
+    pace: Pace,
+    started: Option<Instant>,
+    frames_read: u64,
+}
+
+/* ---------- main things ---------- */
+
+impl<I, T> IterSource<I, T>
+where
+    I: Iterator<Item = T>,
+{
+    pub fn new<S>(samples: S, sample_rate: f64) -> Self
+    where
+        S: IntoIterator<IntoIter = I, Item = T>,
+    {
+        IterSource {
+            samples: samples.into_iter(),
+            chunk_len: DEFAULT_CHUNK_LEN,
+            sample_rate,
+            clock: Clock::new(Pace::Unthrottled),
+            finished: false,
+            data: Vec::with_capacity(DEFAULT_CHUNK_LEN),
+        }
+    }
+
+    /// Hand out at most `len` samples per read when unthrottled.
+    pub fn with_chunk_len(mut self, len: usize) -> Self {
+        self.chunk_len = len.max(1);
+        self
+    }
+
+    /// Hand out samples at the given pace. The default is unthrottled.
+    pub fn with_pace(mut self, pace: Pace) -> Self {
+        self.clock = Clock::new(pace);
+        self
+    }
+}
+
+impl<I, T> AudioSource<T> for IterSource<I, T>
+where
+    I: Iterator<Item = T>,
+{
+    type Error = Infallible;
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn read(&mut self) -> Result<&[T], Self::Error> {
+        let due = self.clock.frames_due(self.sample_rate, self.chunk_len);
+        self.data.clear();
+        self.data.extend((&mut self.samples).take(due));
+        self.clock.advance(self.data.len());
+        self.finished = self.data.len() < due;
+        Ok(&self.data)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/* ---------- pacing ---------- */
+
+impl Clock {
+    pub(crate) fn new(pace: Pace) -> Self {
+        Clock {
+            pace,
+            started: None,
+            frames_read: 0,
+        }
+    }
+
+    /// Number of frames to read now. When unthrottled, this is `chunk`.
+    pub(crate) fn frames_due(&mut self, sample_rate: f64, chunk: usize) -> usize {
+        match self.pace {
+            Pace::Unthrottled => chunk,
+            Pace::RealTime => {
+                let started = *self.started.get_or_insert_with(Instant::now);
+                let played = (started.elapsed().as_secs_f64() * sample_rate) as u64;
+                played.saturating_sub(self.frames_read) as usize
+            }
+        }
+    }
+
+    pub(crate) fn advance(&mut self, frames: usize) {
+        self.frames_read += frames as u64;
+    }
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iter_source_reads_in_chunks_until_exhausted() {
+        let mut source = IterSource::new(0..10, 100.0).with_chunk_len(4);
+        assert_eq!(source.read(), Ok(&[0, 1, 2, 3][..]));
+        assert_eq!(source.read(), Ok(&[4, 5, 6, 7][..]));
+        assert!(!source.is_finished());
+        assert_eq!(source.read(), Ok(&[8, 9][..]));
+        assert!(source.is_finished());
+        assert_eq!(source.read(), Ok(&[][..]));
+        assert_eq!(source.channels(), 1);
+        assert_eq!(source.sample_rate(), 100.0);
+    }
+}
@@ -0,0 +1,392 @@
+use ordered_float::NotNan;
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Float;
+use rustfft::FFTnum;
+
+use crate::analyser::default_vec;
+use crate::detector::{Estimate, PitchDetector};
+use crate::realfft::{fast_len, RealFft};
+use crate::window::{Window, WindowKind};
+
+/* ---------- constants ---------- */
+
+/// How far below the loudest bin the fundamental of a harmonic product
+/// candidate may be, in dB. Any further, and it is most likely a subharmonic
+/// of a tone with fewer strong partials than are multiplied, whose product
+/// only wins on the noise at the missing partials.
+const MAX_FUNDAMENTAL_DROP_DB: f64 = 30.0;
+
+/* ---------- types ---------- */
+
+/// A pitch detector working on the spectrum of a windowed frame.
+///
+/// The frame is padded with zeroes up to the size of the FFT, which gives
+/// more bins than the frame alone would, interpolating the spectrum between
+/// them.
+pub struct Spectral<T> {
+    fft: RealFft<T>,
+    /// Number of samples in a frame, without the padding.
+    frame_len: usize,
+    /// Size of the FFT asked for, if any, see `with_fft_size`.
+    fft_size: Option<usize>,
+    /// The windowed frame, followed by the padding.
+    frame: Vec<T>,
+    /// Bins from zero to the Nyquist frequency.
+    spectrum: Vec<Complex<T>>,
+    window: Window<T>,
+    picking: PeakPicking,
+}
+
+/// How the bin of the fundamental is picked from the spectrum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeakPicking {
+    /// The loudest bin.
+    Loudest,
+    /// The bin with the largest product of magnitudes at its multiples.
+    HarmonicProduct { harmonics: usize },
+}
+
+/* ---------- main things ---------- */
+
+impl<T: FFTnum + Float + Default> Spectral<T> {
+    pub fn new(window: WindowKind, picking: PeakPicking) -> Self {
+        Self::with_sizes(0, None, window, picking)
+    }
+
+    /// Use an FFT of `size` samples. Frames longer than that are padded to
+    /// the next size the FFT is fast at instead, which is also what happens
+    /// with no size given.
+    pub fn with_fft_size(self, size: Option<usize>) -> Self {
+        Self::with_sizes(self.frame_len, size, self.window.kind(), self.picking)
+    }
+
+    fn with_sizes(
+        frame_len: usize,
+        fft_size: Option<usize>,
+        window: WindowKind,
+        picking: PeakPicking,
+    ) -> Self {
+        let fft_len = match fft_size {
+            Some(size) if size >= frame_len => size,
+            _ => fast_len(frame_len),
+        };
+        let fft = RealFft::new(fft_len);
+        Spectral {
+            frame: default_vec(fft_len),
+            spectrum: default_vec(fft.spectrum_len()),
+            fft,
+            frame_len,
+            fft_size,
+            window: Window::new(window, frame_len),
+            picking,
+        }
+    }
+}
+
+impl<T: FFTnum + Float + Default> PitchDetector<T> for Spectral<T> {
+    fn detect(&mut self, samples: &[T], sample_rate: f64) -> Option<Estimate> {
+        if samples.len() != self.frame_len {
+            let (window, picking) = (self.window.kind(), self.picking);
+            *self = Self::with_sizes(samples.len(), self.fft_size, window, picking);
+        }
+        self.do_fft(samples);
+        let index = match self.picking {
+            PeakPicking::Loudest => self.loudest_bin(),
+            PeakPicking::HarmonicProduct { harmonics } => {
+                self.harmonic_product_bin(harmonics)
+            }
+        }?;
+        let fundamental = self.refine_peak(index);
+        Some(Estimate {
+            frequency: fundamental * sample_rate / self.size() as f64,
+            confidence: self.harmonicity(fundamental),
+        })
+    }
+}
+
+impl<T: FFTnum + Float> Spectral<T> {
+    /// Number of samples transformed, which is what the bins are fractions
+    /// of.
+    fn size(&self) -> usize {
+        self.fft.len()
+    }
+
+    /// Distance between the bins, in bins of the frame without the padding.
+    fn spacing(&self) -> f64 {
+        self.frame_len as f64 / self.size() as f64
+    }
+
+    fn do_fft(&mut self, samples: &[T]) {
+        // The padding stays zero from the start.
+        let frame = &mut self.frame[..self.frame_len];
+        frame.copy_from_slice(samples);
+        self.window.apply(frame);
+        self.fft.process(&self.frame, &mut self.spectrum);
+    }
+
+    fn loudest_bin(&self) -> Option<usize> {
+        self.spectrum.iter()
+            .map(Complex::norm)
+            .map(NotNan::new)
+            .enumerate()
+            .take(self.size() / 2)
+            .skip(1)
+            .flat_map(|(i, norm)| norm.map(|nonnan| (i, nonnan)))
+            .max_by_key(|(_, norm)| *norm)
+            .map(|(i, _)| i)
+    }
+
+    fn harmonic_product_bin(&self, harmonics: usize) -> Option<usize> {
+        let norm = |i: usize| self.spectrum[i].norm();
+        let half = self.size() / 2;
+        let loudest = (1..half).map(norm).fold(T::zero(), T::max);
+        let drop = T::from(10.0.powf(-MAX_FUNDAMENTAL_DROP_DB / 20.0));
+        let floor = loudest * drop.unwrap_or_else(T::zero);
+        // The product peak may be off by a bin of the frame from the peak of
+        // the fundamental itself, which is several bins with the padding.
+        let reach = (1.0 / self.spacing()).ceil() as usize;
+        let peak_near = |index: usize| {
+            let upper = (index + reach).min(half - 1);
+            (index.saturating_sub(reach).max(1)..=upper)
+                .flat_map(|i| NotNan::new(norm(i)).ok().map(|n| (i, n)))
+                .max_by_key(|(_, norm)| *norm)
+                .map(|(i, _)| i)
+        };
+        // A bin is half a bin off the fundamental at most, which is h / 2 bins
+        // off the h-th harmonic, so that is how far its peak is looked for.
+        let harmonic = |i: usize, h: usize| {
+            let upper = (i * h + h / 2).min(half - 1);
+            (i * h - h / 2..=upper).map(norm).fold(T::zero(), T::max)
+        };
+        let index = (1..self.size() / (2 * harmonics))
+            .filter(|&i| peak_near(i).is_some_and(|peak| norm(peak) >= floor))
+            .map(|i| {
+                let product = (1..=harmonics)
+                    .map(|h| harmonic(i, h).ln())
+                    .fold(T::zero(), |acc, v| acc + v);
+                (i, NotNan::new(product))
+            })
+            .flat_map(|(i, product)| product.map(|nonnan| (i, nonnan)))
+            .max_by_key(|(_, product)| *product)
+            .map(|(i, _)| i)?;
+        peak_near(index)
+    }
+
+    /// The position of a spectral peak with sub-bin precision, computed from
+    /// the magnitudes of the bin and its neighbours, see
+    /// `Window::peak_offset`.
+    ///
+    /// With the padding, the neighbours are taken about a bin of the frame
+    /// away rather than next door. Bins closer than that differ too little
+    /// to go by, and the top of the flat-top window is not even highest in
+    /// the middle, so the loudest bin may be a few off the peak.
+    fn refine_peak(&self, index: usize) -> f64 {
+        let norm = |i: usize| self.spectrum[i].norm().to_f64();
+        let whole = index as f64;
+        let step = (1.0 / self.spacing()).round().max(1.0) as usize;
+        if index < step || index + step >= self.spectrum.len() {
+            return whole;
+        }
+        match (norm(index - step), norm(index), norm(index + step)) {
+            (Some(left), Some(centre), Some(right)) => {
+                let spacing = step as f64 * self.spacing();
+                let offset = self.window.peak_offset(left, centre, right, spacing);
+                whole + step as f64 * offset
+            }
+            _ => whole,
+        }
+    }
+
+    /// The share of the spectral energy that lies within the main lobes of
+    /// the fundamental and its harmonics.
+    fn harmonicity(&self, fundamental: f64) -> f64 {
+        let half = self.size() / 2;
+        let power = |i: usize| self.spectrum[i].norm_sqr().to_f64().unwrap_or(0.0);
+        let total: f64 = (1..half).map(power).sum();
+        if fundamental < 1.0 || total <= 0.0 {
+            return 0.0;
+        }
+        let lobe = self.window.main_lobe_bins() as f64 / self.spacing();
+        let mut harmonic = 0.0;
+        // First bin not yet accounted for, in case the lobes overlap.
+        let mut next = 1;
+        let mut centre = fundamental;
+        while centre < half as f64 {
+            let low = ((centre - lobe).ceil().max(0.0) as usize).max(next);
+            let high = ((centre + lobe).floor() as usize).min(half - 1);
+            harmonic += (low..=high).map(power).sum::<f64>();
+            next = next.max(high + 1);
+            centre += fundamental;
+        }
+        (harmonic / total).min(1.0)
+    }
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    const RATE: f64 = 44100.0;
+    /// 1750 ms, the default frame of the spectral estimators.
+    const FRAME_LEN: usize = 77175;
+    /// None of these fall on a bin, from the low E of a bass up.
+    const FREQUENCIES: [f64; 5] = [41.2, 82.41, 146.83, 440.27, 1003.7];
+
+    fn sine(frequency: f64) -> Vec<f64> {
+        (0..FRAME_LEN)
+            .map(|i| 0.5 * (2.0 * PI * frequency * i as f64 / RATE + 0.3).sin())
+            .collect()
+    }
+
+    /// Check the estimates for all the frequencies with every window.
+    fn check_accuracy(fft_size: Option<usize>) {
+        for &window in WindowKind::ALL.iter() {
+            let mut spectral =
+                Spectral::new(window, PeakPicking::Loudest).with_fft_size(fft_size);
+            for &frequency in FREQUENCIES.iter() {
+                let estimate = spectral.detect(&sine(frequency), RATE);
+                let estimate = estimate.expect("no estimate for a sine");
+                let cents = 1200.0 * (estimate.frequency / frequency).log2();
+                assert!(
+                    cents.abs() < 1.0,
+                    "{} Hz, {} window, FFT size {:?}: off by {} cents",
+                    frequency,
+                    window.name(),
+                    fft_size,
+                    cents
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn off_bin_sines_unpadded() {
+        check_accuracy(Some(FRAME_LEN));
+    }
+
+    #[test]
+    fn off_bin_sines_padded_to_fast_size() {
+        check_accuracy(None);
+    }
+
+    #[test]
+    fn off_bin_sines_heavily_padded() {
+        check_accuracy(Some(1 << 18));
+    }
+
+    /// Samples of the first partials of `frequency`, with the amplitudes
+    /// given.
+    fn partials(frequency: f64, amplitudes: &[f64]) -> Vec<f64> {
+        (0..FRAME_LEN)
+            .map(|i| {
+                let t = i as f64 / RATE;
+                amplitudes.iter()
+                    .enumerate()
+                    .map(|(n, a)| {
+                        let partial = (n + 1) as f64 * frequency;
+                        a * (2.0 * PI * partial * t + 0.3).sin()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn harmonic_product() -> Spectral<f64> {
+        Spectral::new(WindowKind::Hann, PeakPicking::HarmonicProduct { harmonics: 5 })
+    }
+
+    /// Every semitone from the low E of a bass to C7, as a sawtooth, on the
+    /// default main frame and on the shorter frames for the notes they are
+    /// long enough for.
+    #[test]
+    fn harmonic_product_semitone_sweep() {
+        // 1750, 200 and 50 ms, with the shorter ones only used for notes of
+        // at least ten periods, as the analyser does. Each has a detector of
+        // its own, so that it is not set up afresh for every note.
+        let mut lengths = [FRAME_LEN, 8820, 2205]
+            .iter()
+            .map(|&len| (len, harmonic_product()))
+            .collect::<Vec<_>>();
+        // E1 and C7, in semitones from C0.
+        for semitone in 16..=84 {
+            let frequency = 440.0 * ((semitone - 57) as f64 / 12.0).exp2();
+            // The partials of a sawtooth below the Nyquist frequency, but no
+            // more than a dozen to keep the test quick.
+            let count = ((RATE / 2.0 / frequency) as usize).min(12);
+            let amplitudes = (1..=count).map(|n| 0.3 / n as f64).collect::<Vec<_>>();
+            let samples = partials(frequency, &amplitudes);
+            for (len, spectral) in lengths.iter_mut() {
+                let len = *len;
+                if frequency < 10.0 * RATE / len as f64 {
+                    continue;
+                }
+                let estimate = spectral.detect(&samples[..len], RATE);
+                let estimate = estimate.unwrap_or_else(|| panic!("{} Hz", frequency));
+                let cents = cents(estimate.frequency, frequency);
+                assert!(
+                    cents.abs() < 5.0,
+                    "{} Hz in {} samples: off by {} cents",
+                    frequency,
+                    len,
+                    cents
+                );
+                assert!(
+                    estimate.confidence >= 0.5,
+                    "{} Hz in {} samples: confidence {}",
+                    frequency,
+                    len,
+                    estimate.confidence
+                );
+            }
+        }
+    }
+
+    /// Low strings, whose second harmonic is louder than the fundamental,
+    /// which is what the plain loudest bin gets an octave wrong.
+    #[test]
+    fn harmonic_product_with_a_weak_fundamental() {
+        let amplitudes = [0.2, 0.5, 0.3, 0.2, 0.1];
+        let mut loudest = Spectral::new(WindowKind::Hann, PeakPicking::Loudest);
+        let mut spectral = harmonic_product();
+        for &frequency in [41.2, 55.0, 65.41, 98.0].iter() {
+            let samples = partials(frequency, &amplitudes);
+            let wrong = loudest.detect(&samples, RATE).expect("no estimate");
+            assert!(cents(wrong.frequency, 2.0 * frequency).abs() < 1.0);
+            let estimate = spectral.detect(&samples, RATE).expect("no estimate");
+            let cents = cents(estimate.frequency, frequency);
+            assert!(cents.abs() < 1.0, "{} Hz: off by {} cents", frequency, cents);
+        }
+    }
+
+    fn cents(estimate: f64, frequency: f64) -> f64 {
+        1200.0 * (estimate / frequency).log2()
+    }
+
+    /// Tones with fewer partials than the harmonic product multiplies must
+    /// not come out as a subharmonic.
+    #[test]
+    fn harmonic_product_of_few_partials() {
+        let picking = PeakPicking::HarmonicProduct { harmonics: 5 };
+        let mut spectral = Spectral::new(WindowKind::Hann, picking);
+        let two_partials = |frequency: f64| {
+            let second = sine(2.0 * frequency);
+            let first = sine(frequency).into_iter();
+            first.zip(second).map(|(a, b)| 0.5 * (a + b)).collect::<Vec<_>>()
+        };
+        let tones = vec![
+            (156.0, sine(156.0)),
+            (440.27, sine(440.27)),
+            (41.2, two_partials(41.2)),
+            (110.3, two_partials(110.3)),
+        ];
+        for (frequency, samples) in tones {
+            let estimate = spectral.detect(&samples, RATE).expect("no estimate");
+            let cents = 1200.0 * (estimate.frequency / frequency).log2();
+            assert!(cents.abs() < 1.0, "{} Hz: off by {} cents", frequency, cents);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+
+use crate::detector::Estimate;
+use crate::note::{
+    semitones_from_frequency, Reading, CENTS_PER_SEMITONE, SEMITONES_PER_OCTAVE_F,
+};
+
+/* ---------- constants ---------- */
+
+/// A median this far from the smoothed pitch is a new note rather than a
+/// wobble, and is jumped to right away.
+const JUMP_SEMITONES: f64 = 1.0;
+
+/* ---------- types ---------- */
+
+/// Steadies the stream of estimates before it is shown.
+///
+/// The pitch is first passed through a median filter, which gets rid of
+/// stray octave errors and the like, and then through an exponential one.
+/// The note is only changed once the pitch is well past the boundary between
+/// it and its neighbour.
+pub struct Stabiliser {
+    settings: StabiliserSettings,
+    /// Recent pitches, as log2 of frequency.
+    history: VecDeque<f64>,
+    /// Room to sort the history in, so that taking its median does not
+    /// allocate.
+    sorted: Vec<f64>,
+    smoothed: Option<f64>,
+    /// Semitone offset of the shown note.
+    note: Option<i32>,
+    confidence: f64,
+    missed: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StabiliserSettings {
+    /// Number of estimates the median is taken over.
+    pub median_len: usize,
+    /// Weight of a new value in the exponential smoothing, from 0 (never
+    /// move) to 1 (no smoothing).
+    pub smoothing: f64,
+    /// How far past the half-way point to the neighbouring note the pitch
+    /// has to go for the note to change, in cents.
+    pub hysteresis: f64,
+    /// Number of frames without an estimate for which the last note is kept.
+    pub hold: usize,
+}
+
+/* ---------- main things ---------- */
+
+impl Stabiliser {
+    pub fn new(settings: StabiliserSettings) -> Self {
+        Stabiliser {
+            settings,
+            history: VecDeque::with_capacity(settings.median_len.max(1)),
+            sorted: Vec::with_capacity(settings.median_len.max(1)),
+            smoothed: None,
+            note: None,
+            confidence: 0.0,
+            missed: 0,
+        }
+    }
+
+    /// Take the estimate for the next frame, if any, and return what should
+    /// be shown.
+    pub fn update(
+        &mut self,
+        estimate: Option<Estimate>,
+        reference: f64,
+    ) -> Option<(Reading, Estimate)> {
+        match estimate.filter(|e| e.frequency > 0.0) {
+            Some(estimate) => {
+                self.missed = 0;
+                self.confidence = estimate.confidence;
+                self.push(estimate.frequency.log2());
+            }
+            None => {
+                self.missed += 1;
+                if self.missed > self.settings.hold {
+                    self.reset();
+                }
+            }
+        }
+        self.current(reference)
+    }
+
+    fn push(&mut self, pitch: f64) {
+        if self.history.len() >= self.settings.median_len.max(1) {
+            self.history.pop_front();
+        }
+        self.history.push_back(pitch);
+        let median = median(&self.history, &mut self.sorted);
+        let semitones_apart = |a: f64, b: f64| (a - b).abs() * SEMITONES_PER_OCTAVE_F;
+        self.smoothed = match self.smoothed {
+            Some(old) if semitones_apart(old, median) < JUMP_SEMITONES => {
+                let weight = self.settings.smoothing.clamp(0.0, 1.0);
+                Some(old + weight * (median - old))
+            }
+            _ => Some(median),
+        };
+    }
+
+    fn current(&mut self, reference: f64) -> Option<(Reading, Estimate)> {
+        let frequency = self.smoothed?.exp2();
+        let exact = semitones_from_frequency(frequency, reference)?;
+        let threshold = 0.5 + self.settings.hysteresis / CENTS_PER_SEMITONE;
+        let note = match self.note {
+            Some(note) if (exact - f64::from(note)).abs() <= threshold => note,
+            _ => exact.round() as i32,
+        };
+        self.note = Some(note);
+        let reading = Reading::relative_to(exact, note, reference)?;
+        let estimate = Estimate {
+            frequency,
+            confidence: self.confidence,
+        };
+        Some((reading, estimate))
+    }
+
+    fn reset(&mut self) {
+        self.history.clear();
+        self.smoothed = None;
+        self.note = None;
+    }
+}
+
+/* ---------- helpers ---------- */
+
+/// Median of `values`, which must not be empty, sorted in `sorted`.
+fn median(values: &VecDeque<f64>, sorted: &mut Vec<f64>) -> f64 {
+    sorted.clear();
+    sorted.extend(values.iter().cloned());
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let middle = sorted.len() / 2;
+    match sorted.len() % 2 {
+        0 => 0.5 * (sorted[middle - 1] + sorted[middle]),
+        _ => sorted[middle],
+    }
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFERENCE: f64 = 440.0;
+    /// A4, in semitones from C0.
+    const A4: i32 = 57;
+
+    fn settings(median_len: usize, smoothing: f64, hold: usize) -> StabiliserSettings {
+        StabiliserSettings {
+            median_len,
+            smoothing,
+            hysteresis: 15.0,
+            hold,
+        }
+    }
+
+    /// A frequency this many cents off A4.
+    fn cents_off_a4(cents: f64) -> Option<Estimate> {
+        Some(Estimate {
+            frequency: REFERENCE * (cents / 1200.0).exp2(),
+            confidence: 0.9,
+        })
+    }
+
+    /// The shown note, in semitones from C0, and its cents.
+    fn shown(shown: Option<(Reading, Estimate)>) -> (i32, f64) {
+        let (reading, _) = shown.expect("nothing shown");
+        (reading.position.semitone_offset(), reading.cents)
+    }
+
+    #[test]
+    fn median_of_odd_and_even_lengths() {
+        let mut sorted = Vec::new();
+        let odd = [3.0, 1.0, 2.0].iter().cloned().collect();
+        assert_eq!(median(&odd, &mut sorted), 2.0);
+        let even = [4.0, 1.0, 3.0, 2.0].iter().cloned().collect();
+        assert_eq!(median(&even, &mut sorted), 2.5);
+    }
+
+    #[test]
+    fn median_drops_a_stray_octave() {
+        let mut stabiliser = Stabiliser::new(settings(3, 1.0, 0));
+        for &cents in [0.0, 0.0, 1200.0, 0.0, -1200.0, 0.0].iter() {
+            let (note, cents) = shown(stabiliser.update(cents_off_a4(cents), REFERENCE));
+            assert_eq!(note, A4);
+            assert!(cents.abs() < 1e-6, "{} cents", cents);
+        }
+    }
+
+    #[test]
+    fn exponential_smoothing() {
+        let mut stabiliser = Stabiliser::new(settings(1, 0.5, 0));
+        stabiliser.update(cents_off_a4(0.0), REFERENCE);
+        // Half of the way to 20 cents every time.
+        for &expected in [10.0, 15.0, 17.5].iter() {
+            let (note, cents) = shown(stabiliser.update(cents_off_a4(20.0), REFERENCE));
+            assert_eq!(note, A4);
+            assert!((cents - expected).abs() < 1e-6, "{} cents", cents);
+        }
+        // A semitone or more is a new note, and is not smoothed.
+        let (note, cents) = shown(stabiliser.update(cents_off_a4(300.0), REFERENCE));
+        assert_eq!(note, A4 + 3);
+        assert!(cents.abs() < 1e-6, "{} cents", cents);
+    }
+
+    #[test]
+    fn hysteresis_around_the_half_way_point() {
+        let mut stabiliser = Stabiliser::new(settings(1, 1.0, 0));
+        // The note changes 15 cents past the half-way point, either way.
+        let steps = [
+            (0.0, A4, 0.0),
+            (60.0, A4, 60.0),
+            (64.0, A4, 64.0),
+            (66.0, A4 + 1, -34.0),
+            (40.0, A4 + 1, -60.0),
+            (36.0, A4 + 1, -64.0),
+            (34.0, A4, 34.0),
+        ];
+        for &(pitch, expected_note, expected_cents) in steps.iter() {
+            let (note, cents) = shown(stabiliser.update(cents_off_a4(pitch), REFERENCE));
+            assert_eq!(note, expected_note, "at {} cents", pitch);
+            assert!((cents - expected_cents).abs() < 1e-6, "{} cents", cents);
+        }
+    }
+
+    #[test]
+    fn holding_the_last_note() {
+        let mut stabiliser = Stabiliser::new(settings(3, 0.3, 2));
+        stabiliser.update(cents_off_a4(10.0), REFERENCE);
+        for _ in 0..2 {
+            let (note, cents) = shown(stabiliser.update(None, REFERENCE));
+            assert_eq!(note, A4);
+            assert!((cents - 10.0).abs() < 1e-6, "{} cents", cents);
+        }
+        assert!(stabiliser.update(None, REFERENCE).is_none());
+        // What came before the gap is forgotten.
+        let (_, cents) = shown(stabiliser.update(cents_off_a4(-20.0), REFERENCE));
+        assert!((cents - -20.0).abs() < 1e-6, "{} cents", cents);
+    }
+
+    #[test]
+    fn jittery_pitch_and_a_note_change() {
+        let mut stabiliser = Stabiliser::new(settings(3, 0.3, 4));
+        let jitter = [0.0, 8.0, -6.0, 3.0, -8.0, 5.0, 1200.0, -2.0, 7.0, -5.0];
+        let a4 = jitter.iter().map(|&cents| cents_off_a4(cents));
+        // B4, two semitones up, with a dropout.
+        let b4 = jitter.iter().map(|&cents| cents_off_a4(200.0 + cents));
+        let b4 = b4.clone().take(4).chain(Some(None)).chain(b4.skip(5));
+        let shown = a4
+            .chain(b4)
+            .map(|estimate| shown(stabiliser.update(estimate, REFERENCE)))
+            .collect::<Vec<_>>();
+        for (i, &(note, cents)) in shown.iter().enumerate() {
+            // The median takes two of B4 to move, and then jumps.
+            let expected = if i < jitter.len() + 1 { A4 } else { A4 + 2 };
+            assert_eq!(note, expected, "at frame {}", i);
+            // The smoothed jitter, with the octave error left out.
+            assert!(cents.abs() < 5.0, "{} cents at frame {}", cents, i);
+        }
+    }
+}
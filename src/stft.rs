@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+
+use advanced_collections::circular_buffer::CircularBuffer;
+
+/* ---------- types ---------- */
+
+/// Cuts a stream of samples into overlapping frames for short-time analysis.
+///
+/// A frame is produced every `hop` samples, once there is enough data to fill
+/// the first one.
+pub struct Stft<T> {
+    frame: CircularBuffer<T>,
+    queue: VecDeque<T>,
+    hop: usize,
+    since_last_frame: usize,
+    consumed: u64,
+    sample_rate: f64,
+}
+
+/// The stretch of audio a frame covers, in seconds since the start of the
+/// stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timestamp {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Frame lengths and hop size, in milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameSettings {
+    pub length_millis: usize,
+    pub hop_millis: usize,
+    /// Shorter frames analysed along with the main one, see `Analyser`.
+    /// These are cut from the end of the main frame, so `Stft` itself does
+    /// not look at them.
+    pub shorter_millis: Vec<usize>,
+}
+
+/* ---------- main things ---------- */
+
+impl<T> Stft<T> {
+    pub fn new(settings: &FrameSettings, sample_rate: f64) -> Self {
+        let to_samples = |millis: usize| (sample_rate * millis as f64 / 1000.0) as usize;
+        Stft {
+            frame: CircularBuffer::new(to_samples(settings.length_millis).max(1)),
+            queue: VecDeque::new(),
+            hop: to_samples(settings.hop_millis).max(1),
+            since_last_frame: 0,
+            consumed: 0,
+            sample_rate,
+        }
+    }
+
+    /// Number of samples in a frame.
+    pub fn frame_len(&self) -> usize {
+        self.frame.capacity()
+    }
+
+    /// Queue new samples. They are not looked at until `next_frame` is called.
+    pub fn feed<I: IntoIterator<Item = T>>(&mut self, samples: I) {
+        self.queue.extend(samples);
+    }
+
+    fn timestamp(&self) -> Timestamp {
+        let start = self.consumed - self.frame.len() as u64;
+        Timestamp {
+            start: start as f64 / self.sample_rate,
+            end: self.consumed as f64 / self.sample_rate,
+        }
+    }
+}
+
+impl<T: Clone> Stft<T> {
+    /// Copy the next complete frame into `frame`, oldest sample first, or
+    /// return `None` if there is not enough queued data for it yet.
+    pub fn next_frame(&mut self, frame: &mut [T]) -> Option<Timestamp> {
+        while !self.queue.is_empty() {
+            if self.advance() {
+                for (targ, src) in frame.iter_mut().zip(self.frame.iter()) {
+                    *targ = src.clone();
+                }
+                return Some(self.timestamp());
+            }
+        }
+        None
+    }
+
+    /// Drop all the complete frames queued but the newest one, for when the
+    /// analysis falls behind the audio and only the latest pitch matters.
+    /// Their samples still go through the frame, so it stays continuous.
+    pub fn skip_to_latest(&mut self) {
+        let mut stale = self.queued_frames().saturating_sub(1);
+        while stale > 0 {
+            if self.advance() {
+                stale -= 1;
+            }
+        }
+    }
+
+    /// Move a queued sample into the frame, returning true if that completes
+    /// a frame.
+    fn advance(&mut self) -> bool {
+        match self.queue.pop_front() {
+            Some(sample) => {
+                self.frame.push_back(sample);
+                self.consumed += 1;
+                self.since_last_frame += 1;
+                let due = self.frame.is_full() && self.since_last_frame >= self.hop;
+                if due {
+                    self.since_last_frame = 0;
+                }
+                due
+            }
+            None => false,
+        }
+    }
+
+    /// Number of complete frames in the queued data.
+    fn queued_frames(&self) -> usize {
+        let to_fill = self.frame.capacity() - self.frame.len();
+        let to_hop = self.hop.saturating_sub(self.since_last_frame);
+        let until_first = to_fill.max(to_hop).max(1);
+        match self.queue.len().checked_sub(until_first) {
+            Some(after_first) => 1 + after_first / self.hop,
+            None => 0,
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use offkey::analyser::Analysis;
+use offkey::note::Reading;
+
+/* ---------- types ---------- */
+
+/// Writes the analysis of a recording, one row per frame.
+pub struct Table<W> {
+    out: W,
+    format: Format,
+    /// Whether there is a column with the channel.
+    channels: bool,
+    rows: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+/* ---------- main things ---------- */
+
+impl<W: Write> Table<W> {
+    /// Start a table, with a column for the channel if `channels` is set.
+    pub fn new(mut out: W, format: Format, channels: bool) -> io::Result<Self> {
+        match format {
+            Format::Csv => writeln!(out, "{}", column_names(channels).join(","))?,
+            Format::Json => write!(out, "[")?,
+        }
+        Ok(Table {
+            out,
+            format,
+            channels,
+            rows: 0,
+        })
+    }
+
+    /// Write a row for a frame. The time is that of the middle of the frame.
+    pub fn write_row(&mut self, analysis: &Analysis, reference: f64) -> io::Result<()> {
+        let time = 0.5 * (analysis.time.start + analysis.time.end);
+        let estimate = analysis.estimate;
+        let reading =
+            estimate.and_then(|e| Reading::from_frequency(e.frequency, reference));
+        let channel = analysis.channel.map(|channel| (channel + 1).to_string());
+        let fields = [
+            channel,
+            Some(format!("{:.3}", time)),
+            estimate.map(|e| format!("{:.3}", e.frequency)),
+            reading.map(|r| r.position.to_string()),
+            reading.map(|r| format!("{:.2}", r.cents)),
+            estimate.map(|e| format!("{:.3}", e.confidence)),
+        ];
+        let fields = if self.channels { &fields[..] } else { &fields[1..] };
+        match self.format {
+            Format::Csv => {
+                let fields = fields.iter().map(|f| f.as_deref().unwrap_or(""));
+                writeln!(self.out, "{}", fields.collect::<Vec<_>>().join(","))?;
+            }
+            Format::Json => {
+                let separator = if self.rows == 0 { "" } else { "," };
+                let names = column_names(self.channels);
+                let pairs = names.iter().zip(fields.iter()).map(|(name, field)| {
+                    let value = match field {
+                        // The note is the only field that is not a number.
+                        Some(text) if *name == "note" => format!("\"{}\"", text),
+                        Some(number) => number.clone(),
+                        None => "null".to_string(),
+                    };
+                    format!("\"{}\": {}", name, value)
+                });
+                let object = pairs.collect::<Vec<_>>().join(", ");
+                write!(self.out, "{}\n  {{{}}}", separator, object)?;
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.format == Format::Json {
+            let newline = if self.rows == 0 { "" } else { "\n" };
+            writeln!(self.out, "{}]", newline)?;
+        }
+        self.out.flush()
+    }
+}
+
+/* ---------- formats ---------- */
+
+impl Format {
+    pub const ALL: [Format; 2] = [Format::Csv, Format::Json];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Format::ALL
+            .iter()
+            .find(|format| format.name() == s)
+            .cloned()
+            .ok_or_else(|| {
+                let names = Format::ALL.iter().map(|format| format.name());
+                let names = names.collect::<Vec<_>>().join(", ");
+                format!("unknown output format '{}', expected one of: {}", s, names)
+            })
+    }
+}
+
+/* ---------- helpers ---------- */
+
+fn column_names(channels: bool) -> &'static [&'static str] {
+    let names = &["channel", "time", "frequency", "note", "cents", "confidence"];
+    if channels {
+        names
+    } else {
+        &names[1..]
+    }
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use offkey::detector::Estimate;
+    use offkey::stft::Timestamp;
+
+    use super::*;
+
+    /// A frame 23.45 cents sharp of A4 on the second channel, and a silent
+    /// one on the first.
+    fn analyses() -> [Analysis; 2] {
+        let estimate = Estimate {
+            frequency: 446.0,
+            confidence: 0.9,
+        };
+        [
+            Analysis {
+                channel: Some(1),
+                time: Timestamp { start: 0.0, end: 0.1 },
+                level: -20.0,
+                estimate: Some(estimate),
+            },
+            Analysis {
+                channel: Some(0),
+                time: Timestamp { start: 0.05, end: 0.15 },
+                level: -80.0,
+                estimate: None,
+            },
+        ]
+    }
+
+    /// Everything written for `analyses`.
+    fn table(format: Format, channels: bool, analyses: &[Analysis]) -> String {
+        let mut out = Vec::new();
+        let mut table =
+            Table::new(&mut out, format, channels).expect("writing to memory");
+        for analysis in analyses.iter() {
+            table.write_row(analysis, 440.0).expect("writing to memory");
+        }
+        table.finish().expect("writing to memory");
+        String::from_utf8(out).expect("the table is text")
+    }
+
+    #[test]
+    fn csv() {
+        let expected = "time,frequency,note,cents,confidence\n\
+                        0.050,446.000,A4,23.45,0.900\n\
+                        0.100,,,,\n";
+        assert_eq!(table(Format::Csv, false, &analyses()), expected);
+        let expected = "channel,time,frequency,note,cents,confidence\n\
+                        2,0.050,446.000,A4,23.45,0.900\n\
+                        1,0.100,,,,\n";
+        assert_eq!(table(Format::Csv, true, &analyses()), expected);
+    }
+
+    #[test]
+    fn json() {
+        let expected = "[\n  \
+            {\"time\": 0.050, \"frequency\": 446.000, \"note\": \"A4\", \
+            \"cents\": 23.45, \"confidence\": 0.900},\n  \
+            {\"time\": 0.100, \"frequency\": null, \"note\": null, \
+            \"cents\": null, \"confidence\": null}\n]\n";
+        assert_eq!(table(Format::Json, false, &analyses()), expected);
+        let expected = "[\n  \
+            {\"channel\": 2, \"time\": 0.050, \"frequency\": 446.000, \
+            \"note\": \"A4\", \"cents\": 23.45, \"confidence\": 0.900}\n]\n";
+        assert_eq!(table(Format::Json, true, &analyses()[..1]), expected);
+        assert_eq!(table(Format::Json, true, &[]), "[]\n");
+    }
+}
@@ -5,7 +5,7 @@ use serde::Deserialize;
 use snafu::{ResultExt, Snafu};
 
 use crate::error;
-use crate::note::{
+use offkey::note::{
     Accidental::{self, *},
     Note::{self, *},
     Octave,
@@ -19,10 +19,27 @@ pub struct Text {
     pub low_octave: String,
     pub high_octave: String,
     pub missing_octave: String,
+    pub cents: String,
+    pub hertz: String,
+    pub reference: String,
+    pub confidence: String,
+    pub level: String,
+    pub decibels: String,
+    pub no_signal: String,
+    pub channel: String,
+    pub device: String,
+    pub channels: String,
+    pub overruns: String,
+    pub suspends: String,
+    pub period: String,
+    pub buffer: String,
+    pub dropped: String,
+    pub frames: String,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Snafu)]
 #[snafu(visibility(pub))]
+#[allow(clippy::enum_variant_names)]
 pub enum MissingText {
     #[snafu(display("No text for octave: {}", missing.0))]
     MissingOctave { missing: Octave },
@@ -0,0 +1,331 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use libc::{c_void, pollfd};
+
+use crate::ring::{ring, Consumer, Producer};
+use crate::source::{AudioSource, Xruns};
+
+/* ---------- constants ---------- */
+
+/// Seconds of audio the ring buffer holds, which is how far behind the
+/// capture the analysis may fall before audio is dropped.
+const RING_SECONDS: f64 = 2.0;
+/// Longest wait for a pollable source, after which the thread checks whether
+/// it should stop, in milliseconds.
+const POLL_MILLIS: i32 = 100;
+/// Time between reads of sources that cannot be polled.
+const TIMER: Duration = Duration::from_millis(10);
+
+/* ---------- types ---------- */
+
+/// A source read on a thread of its own, so that slow analysis or drawing
+/// does not hold up the capture.
+///
+/// The thread only reads and hands the samples over through a ring buffer.
+/// It stops when the source runs out, fails or this is dropped. Whenever it
+/// hands something over, it also writes to a pipe, which is what this polls
+/// on.
+pub struct ThreadedSource<T, E> {
+    consumer: Consumer<T>,
+    status: Arc<Status>,
+    signal: Arc<Signal>,
+    thread: Option<JoinHandle<Result<(), E>>>,
+    sample_rate: f64,
+    channels: usize,
+    finished: bool,
+    /// Samples from the latest read.
+    data: Vec<T>,
+}
+
+/// What the capture thread tells the others, apart from the audio.
+#[derive(Default)]
+struct Status {
+    stop: AtomicBool,
+    done: AtomicBool,
+    /// Set once the source has reported its xruns.
+    reported: AtomicBool,
+    overruns: AtomicUsize,
+    suspends: AtomicUsize,
+    period_size: AtomicUsize,
+    buffer_size: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+/// Both ends of a non-blocking pipe, which the capture thread writes to
+/// whenever there is something new to read.
+struct Signal {
+    read: RawFd,
+    write: RawFd,
+}
+
+/* ---------- main things ---------- */
+
+impl<T, E> ThreadedSource<T, E>
+where
+    T: Copy + Default + Send + 'static,
+    E: Send + 'static,
+{
+    /// Move `source` to a new thread and start reading it.
+    pub fn spawn<S>(source: S) -> io::Result<Self>
+    where
+        S: AudioSource<T, Error = E> + Send + 'static,
+    {
+        let sample_rate = source.sample_rate();
+        let channels = source.channels().max(1);
+        let capacity = (sample_rate * RING_SECONDS) as usize * channels;
+        let (producer, consumer) = ring(capacity);
+        let status = Arc::new(Status::default());
+        let signal = Arc::new(Signal::new()?);
+        let thread_status = Arc::clone(&status);
+        let thread_signal = Arc::clone(&signal);
+        let thread = thread::Builder::new()
+            .name("capture".into())
+            .spawn(move || {
+                let res = capture(source, producer, &thread_status, &thread_signal);
+                thread_status.done.store(true, Ordering::Release);
+                thread_signal.raise();
+                res
+            })?;
+        Ok(ThreadedSource {
+            consumer,
+            status,
+            signal,
+            thread: Some(thread),
+            sample_rate,
+            channels,
+            finished: false,
+            data: Vec::new(),
+        })
+    }
+}
+
+impl<T, E> AudioSource<T> for ThreadedSource<T, E>
+where
+    T: Copy,
+{
+    type Error = E;
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Take everything the thread has captured since the last read. Once it
+    /// has stopped and everything is taken, this gives the error it stopped
+    /// with, if any.
+    fn read(&mut self) -> Result<&[T], E> {
+        self.data.clear();
+        // Checked first, so that nothing pushed just before the thread
+        // stopped is missed.
+        let done = self.status.done.load(Ordering::Acquire);
+        self.consumer.pop_into(&mut self.data);
+        if done && self.data.is_empty() {
+            self.finished = true;
+            if let Some(thread) = self.thread.take() {
+                // The thread does not panic, short of a bug in the source.
+                thread.join().expect("the capture thread panicked")?;
+            }
+        }
+        Ok(&self.data)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn poll_descriptors(&self) -> Result<Vec<pollfd>, E> {
+        Ok(vec![pollfd {
+            fd: self.signal.read,
+            events: libc::POLLIN,
+            revents: 0,
+        }])
+    }
+
+    /// Whether there is audio to take or the thread has stopped. The pipe is
+    /// emptied here rather than on reading, as only a poll may have filled
+    /// it, and a read that follows takes whatever came after anyway.
+    fn poll_ready(&self, descriptors: &[pollfd]) -> Result<bool, E> {
+        if descriptors.iter().any(|descriptor| descriptor.revents != 0) {
+            self.signal.clear();
+        }
+        let done = self.status.done.load(Ordering::Acquire);
+        Ok(done || !self.consumer.is_empty())
+    }
+
+    fn xruns(&self) -> Option<Xruns> {
+        let status = &self.status;
+        let dropped = status.dropped.load(Ordering::Relaxed);
+        if !status.reported.load(Ordering::Relaxed) && dropped == 0 {
+            return None;
+        }
+        Some(Xruns {
+            overruns: status.overruns.load(Ordering::Relaxed),
+            suspends: status.suspends.load(Ordering::Relaxed),
+            period_size: status.period_size.load(Ordering::Relaxed),
+            buffer_size: status.buffer_size.load(Ordering::Relaxed),
+            dropped,
+        })
+    }
+}
+
+impl<T, E> Drop for ThreadedSource<T, E> {
+    fn drop(&mut self) {
+        self.status.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            // Whatever it stopped with is of no interest any more.
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Signal {
+    fn new() -> io::Result<Self> {
+        let mut ends = [0; 2];
+        if unsafe { libc::pipe(ends.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Closes the pipe if the rest fails.
+        let signal = Signal {
+            read: ends[0],
+            write: ends[1],
+        };
+        for &end in ends.iter() {
+            let flags = unsafe { libc::fcntl(end, libc::F_GETFL) };
+            if flags < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if unsafe { libc::fcntl(end, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(signal)
+    }
+
+    /// Make the reading end ready. A write that fails because the pipe is
+    /// full is of no concern, as it is ready anyway.
+    fn raise(&self) {
+        let byte = 1u8;
+        unsafe { libc::write(self.write, &byte as *const u8 as *const c_void, 1) };
+    }
+
+    /// Empty the pipe, so that it is not ready until raised again.
+    fn clear(&self) {
+        let mut buf = [0u8; 64];
+        let (ptr, len) = (buf.as_mut_ptr() as *mut c_void, buf.len());
+        while unsafe { libc::read(self.read, ptr, len) } > 0 {}
+    }
+}
+
+impl Drop for Signal {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+/* ---------- helpers ---------- */
+
+/// Read `source` into the ring buffer until told to stop, raising `signal`
+/// whenever something goes in.
+fn capture<T, S>(
+    mut source: S,
+    mut producer: Producer<T>,
+    status: &Status,
+    signal: &Signal,
+) -> Result<(), S::Error>
+where
+    T: Copy,
+    S: AudioSource<T>,
+{
+    let channels = source.channels().max(1);
+    while !status.stop.load(Ordering::Relaxed) && !source.is_finished() {
+        if !wait(&source)? {
+            continue;
+        }
+        match source.read() {
+            Ok(chunk) => {
+                // Only whole frames go in, so that the channels stay in step.
+                let room = producer.free() / channels * channels;
+                let pushed = producer.push(&chunk[..chunk.len().min(room)]);
+                let dropped = (chunk.len() - pushed) / channels;
+                status.dropped.fetch_add(dropped, Ordering::Relaxed);
+                if pushed > 0 {
+                    signal.raise();
+                }
+            }
+            Err(error) => source.recover(error)?,
+        }
+        if let Some(xruns) = source.xruns() {
+            status.overruns.store(xruns.overruns, Ordering::Relaxed);
+            status.suspends.store(xruns.suspends, Ordering::Relaxed);
+            status.period_size.store(xruns.period_size, Ordering::Relaxed);
+            status.buffer_size.store(xruns.buffer_size, Ordering::Relaxed);
+            status.reported.store(true, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// Wait for the source to have something to read, returning false if it
+/// still has nothing after a while.
+fn wait<T, S: AudioSource<T>>(source: &S) -> Result<bool, S::Error> {
+    let mut descriptors = source.poll_descriptors()?;
+    if descriptors.is_empty() {
+        thread::sleep(TIMER);
+        return Ok(true);
+    }
+    let count = descriptors.len() as libc::nfds_t;
+    let ready = unsafe { libc::poll(descriptors.as_mut_ptr(), count, POLL_MILLIS) };
+    match ready {
+        0 => Ok(false),
+        // A failed poll, most likely interrupted by a signal, is left for the
+        // read to sort out.
+        n if n < 0 => Ok(true),
+        _ => source.poll_ready(&descriptors),
+    }
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+    use crate::source::IterSource;
+
+    fn unfailing<T>(error: Infallible) -> T {
+        match error {}
+    }
+
+    /// The pipe wakes the reader up until everything is read, with none of
+    /// it lost on the way.
+    #[test]
+    fn polled_until_finished() {
+        let samples = (0..20_000).map(f64::from).collect::<Vec<_>>();
+        let source = IterSource::new(samples.clone(), 44100.0).with_chunk_len(1000);
+        let mut threaded = ThreadedSource::spawn(source).expect("no capture thread");
+        let mut read = Vec::new();
+        while !threaded.is_finished() {
+            let mut descriptors = threaded.poll_descriptors().unwrap_or_else(unfailing);
+            assert_eq!(descriptors.len(), 1);
+            let ready = unsafe { libc::poll(descriptors.as_mut_ptr(), 1, 1000) };
+            assert!(ready > 0, "nothing to read for a second");
+            if threaded.poll_ready(&descriptors).unwrap_or_else(unfailing) {
+                read.extend_from_slice(threaded.read().unwrap_or_else(unfailing));
+            }
+        }
+        assert_eq!(read, samples);
+        assert_eq!(threaded.xruns(), None);
+    }
+}
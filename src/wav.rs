@@ -0,0 +1,338 @@
+use std::io::{self, Read};
+
+use snafu::{ResultExt, Snafu};
+
+use crate::sample::{ByteOrder, Encoding, FromAnySample, RawFormat};
+use crate::source::{AudioSource, Clock, Pace};
+
+/* ---------- constants ---------- */
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Number of frames read at once when not keeping to real time.
+const CHUNK_FRAMES: usize = 65536;
+/// Length of the longest format chunk, that of `WAVE_FORMAT_EXTENSIBLE`. Any
+/// bytes past it are skipped, whatever length the chunk claims.
+const MAX_FORMAT_LEN: u64 = 40;
+
+/* ---------- types ---------- */
+
+/// A source of audio from a RIFF WAVE stream.
+pub struct WavSource<R, T> {
+    reader: R,
+    format: WavFormat,
+    /// Bytes of sample data not yet read.
+    remaining: u64,
+    clock: Clock,
+    bytes: Vec<u8>,
+    /// Samples from the latest read.
+    data: Vec<T>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WavFormat {
+    /// How samples are stored in the file.
+    pub samples: RawFormat,
+    pub channels: usize,
+    pub sample_rate: u32,
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum Error {
+    #[snafu(display("Failed to read WAV data: {}", source))]
+    Io { source: io::Error },
+    #[snafu(display("Not a RIFF WAVE file"))]
+    NotWave,
+    #[snafu(display("No '{}' chunk in the WAV file", chunk))]
+    MissingChunk { chunk: &'static str },
+    #[snafu(display("Unsupported WAV sample format {} with {} bits", tag, bits))]
+    UnsupportedFormat { tag: u16, bits: u16 },
+    #[snafu(display("Invalid WAV format chunk"))]
+    InvalidFormat,
+}
+
+/* ---------- main things ---------- */
+
+impl<R: Read, T> WavSource<R, T> {
+    /// Read the header of a WAV stream, stopping at the start of the samples.
+    pub fn new(mut reader: R, pace: Pace) -> Result<Self, Error> {
+        let mut riff = [0; 12];
+        reader.read_exact(&mut riff).context(Io)?;
+        if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+            return Err(Error::NotWave);
+        }
+        let mut format = None;
+        loop {
+            let mut header = [0; 8];
+            reader.read_exact(&mut header).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => Error::MissingChunk { chunk: "data" },
+                _ => Error::Io { source: e },
+            })?;
+            let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            // Chunks are padded to an even length.
+            let padded = u64::from(len) + u64::from(len % 2);
+            match &header[0..4] {
+                b"fmt " => {
+                    let mut body = Vec::new();
+                    (&mut reader)
+                        .take(padded.min(MAX_FORMAT_LEN))
+                        .read_to_end(&mut body)
+                        .context(Io)?;
+                    skip(&mut reader, padded - body.len() as u64)?;
+                    format = Some(WavFormat::parse(&body)?);
+                }
+                b"data" => {
+                    let format = format.ok_or(Error::MissingChunk { chunk: "fmt " })?;
+                    return Ok(WavSource {
+                        reader,
+                        format,
+                        remaining: u64::from(len),
+                        clock: Clock::new(pace),
+                        bytes: Vec::new(),
+                        data: Vec::new(),
+                    });
+                }
+                _ => skip(&mut reader, padded)?,
+            }
+        }
+    }
+}
+
+impl<R, T> WavSource<R, T> {
+    /// Number of frames to read now to keep to the chosen pace.
+    fn frames_due(&mut self) -> usize {
+        let available = (self.remaining / self.format.frame_size() as u64) as usize;
+        let rate = f64::from(self.format.sample_rate);
+        let due = self.clock.frames_due(rate, CHUNK_FRAMES);
+        due.min(available)
+    }
+}
+
+impl<R: Read, T: FromAnySample> AudioSource<T> for WavSource<R, T> {
+    type Error = Error;
+
+    fn sample_rate(&self) -> f64 {
+        f64::from(self.format.sample_rate)
+    }
+
+    fn channels(&self) -> usize {
+        self.format.channels
+    }
+
+    /// Read the samples that are due at the chosen pace.
+    fn read(&mut self) -> Result<&[T], Error> {
+        let frame_size = self.format.frame_size();
+        let wanted = (self.frames_due() * frame_size) as u64;
+        self.bytes.clear();
+        (&mut self.reader)
+            .take(wanted)
+            .read_to_end(&mut self.bytes)
+            .context(Io)?;
+        if (self.bytes.len() as u64) < wanted {
+            // The data chunk is shorter than it claims to be, as it is in a
+            // file cut short while being written. That is the end of it.
+            self.remaining = 0;
+        } else {
+            self.remaining -= wanted;
+        }
+        let frames = self.bytes.len() / frame_size;
+        self.bytes.truncate(frames * frame_size);
+        self.clock.advance(frames);
+        self.data.clear();
+        self.format.samples.decode(&self.bytes, &mut self.data);
+        Ok(&self.data)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.remaining < self.format.frame_size() as u64
+    }
+}
+
+/* ---------- format ---------- */
+
+impl WavFormat {
+    fn parse(body: &[u8]) -> Result<Self, Error> {
+        if body.len() < 16 {
+            return Err(Error::InvalidFormat);
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+        let u32_at = |i: usize| {
+            u32::from_le_bytes([body[i], body[i + 1], body[i + 2], body[i + 3]])
+        };
+        let mut tag = u16_at(0);
+        let channels = u16_at(2);
+        let sample_rate = u32_at(4);
+        let bits = u16_at(14);
+        if tag == FORMAT_EXTENSIBLE {
+            // The actual format is the first two bytes of the sub-format GUID.
+            if body.len() < 26 {
+                return Err(Error::InvalidFormat);
+            }
+            tag = u16_at(24);
+        }
+        // Eight-bit samples are the only unsigned ones.
+        let encoding = match (tag, bits) {
+            (FORMAT_PCM, 8) => Encoding::Unsigned,
+            (FORMAT_PCM, 16) | (FORMAT_PCM, 24) | (FORMAT_PCM, 32) => Encoding::Signed,
+            (FORMAT_FLOAT, 32) | (FORMAT_FLOAT, 64) => Encoding::Float,
+            _ => return Err(Error::UnsupportedFormat { tag, bits }),
+        };
+        if channels == 0 || sample_rate == 0 {
+            return Err(Error::InvalidFormat);
+        }
+        Ok(WavFormat {
+            samples: RawFormat::new(encoding, usize::from(bits / 8), ByteOrder::Little),
+            channels: usize::from(channels),
+            sample_rate,
+        })
+    }
+
+    /// Size of one frame (a sample for each channel), in bytes.
+    pub fn frame_size(&self) -> usize {
+        self.samples.width * self.channels
+    }
+}
+
+/* ---------- helpers ---------- */
+
+/// Read and drop `len` bytes, or as many as there are.
+fn skip<R: Read>(reader: &mut R, len: u64) -> Result<(), Error> {
+    io::copy(&mut reader.take(len), &mut io::sink()).context(Io)?;
+    Ok(())
+}
+
+/* ---------- tests ---------- */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 8000;
+
+    /// A chunk with its id, length and body, padded to an even length.
+    fn chunk(id: &[u8], len: u32, body: &[u8]) -> Vec<u8> {
+        let mut res = id.to_vec();
+        res.extend_from_slice(&len.to_le_bytes());
+        res.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            res.push(0);
+        }
+        res
+    }
+
+    /// A format chunk body without the extension.
+    fn format(tag: u16, channels: u16, bits: u16) -> Vec<u8> {
+        let block = u32::from(channels * bits / 8);
+        let mut res = tag.to_le_bytes().to_vec();
+        res.extend_from_slice(&channels.to_le_bytes());
+        res.extend_from_slice(&RATE.to_le_bytes());
+        res.extend_from_slice(&(block * RATE).to_le_bytes());
+        res.extend_from_slice(&(block as u16).to_le_bytes());
+        res.extend_from_slice(&bits.to_le_bytes());
+        res
+    }
+
+    /// A whole file made of `chunks`.
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut res = b"RIFF".to_vec();
+        res.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        res.extend_from_slice(b"WAVE");
+        res.extend_from_slice(&body);
+        res
+    }
+
+    /// A file with the given format chunk body and samples.
+    fn wav(format: &[u8], data: &[u8]) -> Vec<u8> {
+        riff(&[
+            chunk(b"fmt ", format.len() as u32, format),
+            chunk(b"data", data.len() as u32, data),
+        ])
+    }
+
+    /// The format of the file and all the samples in it.
+    fn read_all(file: &[u8]) -> (WavFormat, Vec<f64>) {
+        let mut source = WavSource::new(file, Pace::Unthrottled).expect("a valid file");
+        let mut samples = Vec::new();
+        while !source.is_finished() {
+            samples.extend_from_slice(source.read().expect("a valid file"));
+        }
+        (source.format, samples)
+    }
+
+    #[test]
+    fn pcm16() {
+        let file = wav(&format(FORMAT_PCM, 2, 16), &[0x00, 0x40, 0x00, 0xc0, 0xff, 0x7f]);
+        let (format, samples) = read_all(&file);
+        assert_eq!(format.channels, 2);
+        assert_eq!(format.sample_rate, RATE);
+        let s16_le = RawFormat::new(Encoding::Signed, 2, ByteOrder::Little);
+        assert_eq!(format.samples, s16_le);
+        // The odd sample out is not a whole frame.
+        assert_eq!(samples, [0.5, -0.5]);
+    }
+
+    #[test]
+    fn pcm24_after_other_chunks() {
+        let list = chunk(b"LIST", 5, b"INFOx");
+        let fmt = format(FORMAT_PCM, 1, 24);
+        let data = [0x00, 0x00, 0x40, 0x00, 0x00, 0x80];
+        let file = riff(&[list, chunk(b"fmt ", 16, &fmt), chunk(b"data", 6, &data)]);
+        let (format, samples) = read_all(&file);
+        assert_eq!(format.samples.width, 3);
+        assert_eq!(samples, [0.5, -1.0]);
+    }
+
+    #[test]
+    fn float() {
+        let mut data = 0.25f32.to_le_bytes().to_vec();
+        data.extend_from_slice(&(-0.75f32).to_le_bytes());
+        let (format, samples) = read_all(&wav(&format(FORMAT_FLOAT, 1, 32), &data));
+        assert_eq!(format.samples.encoding, Encoding::Float);
+        assert_eq!(samples, [0.25, -0.75]);
+    }
+
+    #[test]
+    fn extensible() {
+        let mut fmt = format(FORMAT_EXTENSIBLE, 1, 16);
+        // Extension size, valid bits and channel mask.
+        fmt.extend_from_slice(&[22, 0, 16, 0, 4, 0, 0, 0]);
+        // KSDATAFORMAT_SUBTYPE_PCM.
+        fmt.extend_from_slice(&[
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00,
+            0x38, 0x9b, 0x71,
+        ]);
+        assert_eq!(fmt.len(), 40);
+        let (format, samples) = read_all(&wav(&fmt, &[0x00, 0x20]));
+        assert_eq!(format.samples.encoding, Encoding::Signed);
+        assert_eq!(samples, [0.25]);
+    }
+
+    #[test]
+    fn long_format_chunks() {
+        // Whatever follows the known fields is skipped.
+        let mut fmt = format(FORMAT_PCM, 1, 16);
+        fmt.resize(1000, 0xaa);
+        let (_, samples) = read_all(&wav(&fmt, &[0x00, 0x40]));
+        assert_eq!(samples, [0.5]);
+        // One claiming to be longer than the file is not read into memory.
+        let fmt = format(FORMAT_PCM, 1, 16);
+        let file = riff(&[chunk(b"fmt ", u32::MAX - 1, &fmt)]);
+        match WavSource::<_, f64>::new(&file[..], Pace::Unthrottled) {
+            Err(Error::MissingChunk { chunk: "data" }) => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("a file without data"),
+        }
+    }
+
+    #[test]
+    fn short_data_chunks_end_the_file() {
+        let fmt = format(FORMAT_PCM, 1, 16);
+        let data = [0x00, 0x40, 0x00, 0xc0, 0x12];
+        let file = riff(&[chunk(b"fmt ", 16, &fmt), chunk(b"data", 1000, &data)]);
+        let (_, samples) = read_all(&file[..file.len() - 1]);
+        assert_eq!(samples, [0.5, -0.5]);
+    }
+}
@@ -0,0 +1,185 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use rustfft::num_traits::Float;
+
+/* ---------- types ---------- */
+
+/// The shape of a window function.
+///
+/// All of these are cosine sums, and are generated in their periodic
+/// (DFT-even) form.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowKind {
+    Rectangular,
+    Hann,
+    Hamming,
+    BlackmanHarris,
+    FlatTop,
+}
+
+/// A window function of a particular size, ready to be applied.
+pub struct Window<T> {
+    kind: WindowKind,
+    values: Vec<T>,
+}
+
+/* ---------- window kinds ---------- */
+
+impl WindowKind {
+    pub const ALL: [WindowKind; 5] = [
+        WindowKind::Rectangular,
+        WindowKind::Hann,
+        WindowKind::Hamming,
+        WindowKind::BlackmanHarris,
+        WindowKind::FlatTop,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowKind::Rectangular => "rectangular",
+            WindowKind::Hann => "hann",
+            WindowKind::Hamming => "hamming",
+            WindowKind::BlackmanHarris => "blackman-harris",
+            WindowKind::FlatTop => "flat-top",
+        }
+    }
+
+    /// Coefficients of the cosine terms, starting with the constant one.
+    fn coefficients(self) -> &'static [f64] {
+        match self {
+            WindowKind::Rectangular => &[1.0],
+            WindowKind::Hann => &[0.5, 0.5],
+            WindowKind::Hamming => &[0.54, 0.46],
+            WindowKind::BlackmanHarris => &[0.358_75, 0.488_29, 0.141_28, 0.011_68],
+            WindowKind::FlatTop => &[
+                0.215_578_95,
+                0.416_631_58,
+                0.277_263_158,
+                0.083_578_947,
+                0.006_947_368,
+            ],
+        }
+    }
+
+    /// Value of the window at `index` out of `size`.
+    fn value(self, index: usize, size: usize) -> f64 {
+        let phase = 2.0 * PI * index as f64 / size as f64;
+        self.coefficients()
+            .iter()
+            .enumerate()
+            .map(|(k, a)| alternate(k) * a * (phase * k as f64).cos())
+            .sum()
+    }
+
+    /// Spectrum of the window at a distance of `bins` from its centre, with
+    /// the common `sin(pi * bins) / pi` factor taken out.
+    fn kernel(self, bins: f64) -> f64 {
+        let coefs = self.coefficients();
+        coefs[1..]
+            .iter()
+            .enumerate()
+            .map(|(k, a)| {
+                let k = k + 1;
+                let kf = k as f64;
+                alternate(k) * a / 2.0 * (1.0 / (bins + kf) + 1.0 / (bins - kf))
+            })
+            .fold(coefs[0] / bins, |acc, term| acc + term)
+    }
+
+    /// Magnitude of the spectrum of the window at a distance of `bins` from
+    /// its centre.
+    fn response(self, bins: f64) -> f64 {
+        ((PI * bins).sin() / PI * self.kernel(bins)).abs()
+    }
+}
+
+impl FromStr for WindowKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        WindowKind::ALL
+            .iter()
+            .find(|kind| kind.name() == s)
+            .cloned()
+            .ok_or_else(|| {
+                let names = WindowKind::ALL.iter().map(|kind| kind.name());
+                let names = names.collect::<Vec<_>>().join(", ");
+                format!("unknown window '{}', expected one of: {}", s, names)
+            })
+    }
+}
+
+/* ---------- windows ---------- */
+
+impl<T: Float> Window<T> {
+    pub fn new(kind: WindowKind, size: usize) -> Self {
+        let values = (0..size)
+            .map(|i| T::from(kind.value(i, size)).unwrap_or_else(T::one))
+            .collect();
+        Window { kind, values }
+    }
+
+    pub fn apply(&self, data: &mut [T]) {
+        for (sample, &weight) in data.iter_mut().zip(self.values.iter()) {
+            *sample = *sample * weight;
+        }
+    }
+}
+
+impl<T> Window<T> {
+    pub fn kind(&self) -> WindowKind {
+        self.kind
+    }
+
+    /// Half-width of the main lobe of the window's spectrum, in bins.
+    pub fn main_lobe_bins(&self) -> usize {
+        self.kind.coefficients().len()
+    }
+
+    /// Offset of the true peak of a windowed sinusoid from its loudest bin,
+    /// in bins.
+    ///
+    /// The arguments are the magnitudes of the loudest bin and of its
+    /// neighbours, and the distance between bins as a fraction of the bin
+    /// width of the window itself, which is 1 unless the frame was padded
+    /// with zeroes. The ratio of the louder neighbour to the peak is matched
+    /// against the spectrum of the window, which is exact for a lone
+    /// sinusoid, barring the leakage from its negative frequency image.
+    pub fn peak_offset(&self, left: f64, centre: f64, right: f64, spacing: f64) -> f64 {
+        const ITERATIONS: usize = 50;
+        let (direction, neighbour) = if right > left {
+            (1.0, right)
+        } else {
+            (-1.0, left)
+        };
+        let target = neighbour / centre;
+        if !target.is_finite() || spacing <= 0.0 {
+            return 0.0;
+        }
+        let ratio = |offset: f64| {
+            self.kind.response(offset - spacing) / self.kind.response(offset)
+        };
+        // The ratio grows from its minimum at zero offset to 1 half-way
+        // between the bins.
+        let (mut low, mut high) = (f64::EPSILON, 0.5 * spacing);
+        for _ in 0..ITERATIONS {
+            let middle = 0.5 * (low + high);
+            if ratio(middle) < target {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        direction * 0.5 * (low + high) / spacing
+    }
+}
+
+/* ---------- helpers ---------- */
+
+fn alternate(k: usize) -> f64 {
+    match k % 2 {
+        0 => 1.0,
+        _ => -1.0,
+    }
+}